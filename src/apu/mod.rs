@@ -0,0 +1,355 @@
+mod dmc;
+mod envelope;
+mod length_counter;
+mod noise;
+pub mod pulse;
+mod triangle;
+
+use std::mem;
+
+use self::dmc::Dmc;
+use self::noise::Noise;
+use self::pulse::{Pulse, PulseChannel};
+use self::triangle::Triangle;
+
+pub const CPU_FREQUENCY: u32 = 1789773;
+pub const SAMPLE_RATE: u32 = 44100;
+/// How far one step of a pulse's volume moves the mixer's output, from the usual linear
+/// approximation of the pulse mix. Expansion audio has no fixed level against the APU, so
+/// each chip's scale is derived from this instead of being picked by ear: a channel at
+/// full volume is mixed about as loud as a pulse at full volume, 15 steps.
+pub const PULSE_STEP_LEVEL: f32 = 0.00752;
+
+const STATUS_PULSE1: u8 = 0x01;
+const STATUS_PULSE2: u8 = 0x02;
+const STATUS_TRIANGLE: u8 = 0x04;
+const STATUS_NOISE: u8 = 0x08;
+const STATUS_DMC: u8 = 0x10;
+const STATUS_FRAME_INTERRUPT: u8 = 0x40;
+const STATUS_DMC_INTERRUPT: u8 = 0x80;
+const FRAME_COUNTER_IRQ_INHIBIT_FLAG: u8 = 0x40;
+const FRAME_COUNTER_MODE_FLAG: u8 = 0x80;
+
+// One-pole high-pass at roughly 90Hz, matching the first filter in the console's output
+// stage, which also removes the mixer's DC offset.
+const HIGH_PASS_COEFFICIENT: f32 = 0.987;
+
+pub struct Apu {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    /// The cartridge's expansion audio output for the current cycle, already on the
+    /// mixer's scale.
+    pub expansion_audio: f32,
+    cycle: u64,
+    frame_counter_cycle: u32,
+    five_step_mode: bool,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+    sample_phase: u32,
+    sample_sum: f32,
+    sample_count: u32,
+    filter_input: f32,
+    filter_output: f32,
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        let mut pulse_table = [0f32; 31];
+        for (n, entry) in pulse_table.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+
+        let mut tnd_table = [0f32; 203];
+        for (n, entry) in tnd_table.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+
+        Apu {
+            pulse1: Pulse::new(PulseChannel::One),
+            pulse2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            expansion_audio: 0.0,
+            cycle: 0,
+            frame_counter_cycle: 0,
+            five_step_mode: false,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            pulse_table: pulse_table,
+            tnd_table: tnd_table,
+            sample_phase: 0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filter_input: 0.0,
+            filter_output: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+
+        if self.pulse1.length_counter.active() {
+            status |= STATUS_PULSE1;
+        }
+        if self.pulse2.length_counter.active() {
+            status |= STATUS_PULSE2;
+        }
+        if self.triangle.length_counter.active() {
+            status |= STATUS_TRIANGLE;
+        }
+        if self.noise.length_counter.active() {
+            status |= STATUS_NOISE;
+        }
+        if self.dmc.active() {
+            status |= STATUS_DMC;
+        }
+        if self.frame_irq {
+            status |= STATUS_FRAME_INTERRUPT;
+        }
+        if self.dmc.irq {
+            status |= STATUS_DMC_INTERRUPT;
+        }
+
+        self.frame_irq = false;
+
+        status
+    }
+
+    pub fn write_status(&mut self, value: u8) {
+        self.pulse1.length_counter.set_enabled(value & STATUS_PULSE1 != 0);
+        self.pulse2.length_counter.set_enabled(value & STATUS_PULSE2 != 0);
+        self.triangle.length_counter.set_enabled(value & STATUS_TRIANGLE != 0);
+        self.noise.length_counter.set_enabled(value & STATUS_NOISE != 0);
+        self.dmc.set_enabled(value & STATUS_DMC != 0);
+    }
+
+    pub fn write_frame_counter(&mut self, value: u8) {
+        self.five_step_mode = value & FRAME_COUNTER_MODE_FLAG != 0;
+        self.frame_irq_inhibit = value & FRAME_COUNTER_IRQ_INHIBIT_FLAG != 0;
+        self.frame_counter_cycle = 0;
+
+        if self.frame_irq_inhibit {
+            self.frame_irq = false;
+        }
+
+        if self.five_step_mode {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+    }
+
+    /// Advances the APU by one CPU cycle.
+    pub fn step(&mut self) {
+        self.cycle += 1;
+        self.step_frame_counter();
+
+        if self.cycle % 2 == 0 {
+            self.pulse1.step_timer();
+            self.pulse2.step_timer();
+        }
+        self.triangle.step_timer();
+        self.noise.step_timer();
+        self.dmc.step_timer();
+
+        self.generate_sample();
+    }
+
+    /// The state of the APU's IRQ output, which stays asserted until acknowledged.
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// Returns the samples produced since the last call, at `SAMPLE_RATE` in the range
+    /// -1.0 to 1.0.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        mem::replace(&mut self.samples, Vec::new())
+    }
+
+    fn step_frame_counter(&mut self) {
+        self.frame_counter_cycle += 1;
+
+        match (self.five_step_mode, self.frame_counter_cycle) {
+            (_, 7457) | (_, 22371) => self.clock_quarter_frame(),
+            (_, 14913) | (true, 37281) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (false, 29828) => self.set_frame_irq(),
+            (false, 29829) => {
+                self.set_frame_irq();
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (false, 29830) => {
+                self.set_frame_irq();
+                self.frame_counter_cycle = 0;
+            }
+            (true, 37282) => self.frame_counter_cycle = 0,
+            _ => {}
+        }
+    }
+
+    fn set_frame_irq(&mut self) {
+        if !self.frame_irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    fn output(&self) -> f32 {
+        let pulse = self.pulse1.output() + self.pulse2.output();
+        let tnd = 3 * self.triangle.output() as usize + 2 * self.noise.output() as usize +
+                  self.dmc.output() as usize;
+
+        self.pulse_table[pulse as usize] + self.tnd_table[tnd] + self.expansion_audio
+    }
+
+    fn generate_sample(&mut self) {
+        self.sample_sum += self.output();
+        self.sample_count += 1;
+
+        self.sample_phase += SAMPLE_RATE;
+        if self.sample_phase < CPU_FREQUENCY {
+            return;
+        }
+        self.sample_phase -= CPU_FREQUENCY;
+
+        let input = self.sample_sum / self.sample_count as f32;
+        self.filter_output = HIGH_PASS_COEFFICIENT *
+                             (self.filter_output + input - self.filter_input);
+        self.filter_input = input;
+        self.samples.push(self.filter_output);
+
+        self.sample_sum = 0.0;
+        self.sample_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_status() {
+        let mut apu = Apu::new();
+
+        apu.pulse1.write_timer_high(0x08);
+        assert_eq!(apu.read_status(), 0);
+
+        apu.write_status(STATUS_PULSE1 | STATUS_NOISE);
+        apu.pulse1.write_timer_high(0x08);
+        apu.noise.write_length(0x08);
+        assert_eq!(apu.read_status(), STATUS_PULSE1 | STATUS_NOISE);
+
+        apu.write_status(STATUS_NOISE);
+        assert_eq!(apu.read_status(), STATUS_NOISE);
+    }
+
+    #[test]
+    fn test_frame_counter_clocks_length_counters() {
+        let mut apu = Apu::new();
+
+        apu.write_status(STATUS_PULSE1);
+        // Length index 3 loads a count of 2.
+        apu.pulse1.write_timer_high(0x18);
+
+        for _ in 0..14913 {
+            apu.step();
+        }
+        assert_eq!(apu.read_status() & STATUS_PULSE1, STATUS_PULSE1);
+
+        for _ in 14913..29829 {
+            apu.step();
+        }
+        assert_eq!(apu.read_status() & STATUS_PULSE1, 0);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new();
+
+        for _ in 0..29827 {
+            apu.step();
+        }
+        assert!(!apu.irq());
+
+        apu.step();
+        assert!(apu.irq());
+        assert_eq!(apu.read_status(), STATUS_FRAME_INTERRUPT);
+        assert!(!apu.irq());
+
+        apu.write_frame_counter(FRAME_COUNTER_IRQ_INHIBIT_FLAG);
+        for _ in 0..29830 {
+            apu.step();
+        }
+        assert!(!apu.irq());
+
+        apu.write_frame_counter(FRAME_COUNTER_MODE_FLAG);
+        for _ in 0..37282 {
+            apu.step();
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_dmc_playback() {
+        let mut apu = Apu::new();
+
+        apu.dmc.write_control(0x8f);
+        apu.dmc.write_direct_load(0x40);
+        apu.dmc.write_sample_address(0x10);
+        apu.dmc.write_sample_length(0x00);
+        apu.write_status(STATUS_DMC);
+
+        assert_eq!(apu.read_status(), STATUS_DMC);
+        assert_eq!(apu.dmc.dma_request(), Some(0xc400));
+
+        apu.dmc.fill_sample_buffer(0xff);
+        assert_eq!(apu.dmc.dma_request(), None);
+        assert_eq!(apu.read_status(), STATUS_DMC_INTERRUPT);
+        assert!(apu.irq());
+
+        // The silent shift register drains for eight output cycles before the buffer is
+        // loaded, after which every set bit raises the level by two.
+        for _ in 0..54 * 16 {
+            apu.step();
+        }
+        assert_eq!(apu.dmc.output(), 0x50);
+
+        apu.write_status(0);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = Apu::new();
+
+        for _ in 0..CPU_FREQUENCY {
+            apu.step();
+        }
+
+        assert_eq!(apu.take_samples().len(), SAMPLE_RATE as usize);
+        assert!(apu.take_samples().is_empty());
+    }
+}
@@ -0,0 +1,70 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+static PERIOD_TABLE: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016,
+                                  2034, 4068];
+
+pub struct Noise {
+    mode: bool,
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+    envelope: Envelope,
+    pub length_counter: LengthCounter,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            mode: false,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            shift_register: 1,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.length_counter.set_halt(value & 0x20 != 0);
+        self.envelope.write(value);
+    }
+
+    pub fn write_period(&mut self, value: u8) {
+        self.mode = value & 0x80 != 0;
+        self.timer_period = PERIOD_TABLE[(value & 0x0f) as usize];
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length_counter.load(value >> 3);
+        self.envelope.restart();
+    }
+
+    pub fn step_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 0x01;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.active() || self.shift_register & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+static DUTY_TABLE: [[u8; 8]; 4] = [[0, 1, 0, 0, 0, 0, 0, 0],
+                                   [0, 1, 1, 0, 0, 0, 0, 0],
+                                   [0, 1, 1, 1, 1, 0, 0, 0],
+                                   [1, 0, 0, 1, 1, 1, 1, 1]];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PulseChannel {
+    One,
+    Two,
+    /// One of the MMC5's pulses, which have no sweep unit to mute them.
+    Mmc5,
+}
+
+pub struct Pulse {
+    channel: PulseChannel,
+    duty: u8,
+    sequence_pos: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    pub length_counter: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Pulse {
+        Pulse {
+            channel: channel,
+            duty: 0,
+            sequence_pos: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0,
+        }
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length_counter.set_halt(value & 0x20 != 0);
+        self.envelope.write(value);
+    }
+
+    pub fn write_sweep(&mut self, value: u8) {
+        self.sweep_enabled = value & 0x80 != 0;
+        self.sweep_period = (value >> 4) & 0x07;
+        self.sweep_negate = value & 0x08 != 0;
+        self.sweep_shift = value & 0x07;
+        self.sweep_reload = true;
+    }
+
+    pub fn write_timer_low(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0x0700) | value as u16;
+    }
+
+    pub fn write_timer_high(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | ((value as u16) & 0x07) << 8;
+        self.length_counter.load(value >> 3);
+        self.sequence_pos = 0;
+        self.envelope.restart();
+    }
+
+    pub fn step_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_pos = (self.sequence_pos + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 &&
+           !self.muted() {
+            self.timer_period = self.sweep_target_period();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.active() || self.muted() ||
+           DUTY_TABLE[self.duty as usize][self.sequence_pos as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+
+        if self.sweep_negate {
+            // Pulse 1 negates with one's complement, pulse 2 with two's complement.
+            match self.channel {
+                PulseChannel::One => self.timer_period.saturating_sub(change + 1),
+                PulseChannel::Two | PulseChannel::Mmc5 => {
+                    self.timer_period.saturating_sub(change)
+                }
+            }
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.channel != PulseChannel::Mmc5 &&
+        (self.timer_period < 8 || self.sweep_target_period() > 0x7ff)
+    }
+}
@@ -0,0 +1,83 @@
+use super::length_counter::LengthCounter;
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+pub struct Triangle {
+    timer_period: u16,
+    timer: u16,
+    sequence_pos: u8,
+    pub length_counter: LengthCounter,
+    control_flag: bool,
+    linear_counter: u8,
+    linear_counter_period: u8,
+    linear_counter_reload: bool,
+}
+
+impl Triangle {
+    pub fn new() -> Triangle {
+        Triangle {
+            timer_period: 0,
+            timer: 0,
+            sequence_pos: 0,
+            length_counter: LengthCounter::new(),
+            control_flag: false,
+            linear_counter: 0,
+            linear_counter_period: 0,
+            linear_counter_reload: false,
+        }
+    }
+
+    pub fn write_linear_counter(&mut self, value: u8) {
+        self.control_flag = value & 0x80 != 0;
+        self.length_counter.set_halt(self.control_flag);
+        self.linear_counter_period = value & 0x7f;
+    }
+
+    pub fn write_timer_low(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0x0700) | value as u16;
+    }
+
+    pub fn write_timer_high(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | ((value as u16) & 0x07) << 8;
+        self.length_counter.load(value >> 3);
+        self.linear_counter_reload = true;
+    }
+
+    pub fn step_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+
+            // Ultrasonic periods are silenced by freezing the sequencer instead of
+            // letting it alias into audible pops.
+            if self.length_counter.active() && self.linear_counter > 0 && self.timer_period >= 2 {
+                self.sequence_pos = (self.sequence_pos + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control_flag {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_pos as usize]
+    }
+}
@@ -13,6 +13,7 @@ pub const NEGATIVE_FLAG: u8 = 0x80;
 
 pub const RESET_VECTOR: u16 = 0xfffc;
 pub const BREAK_VECTOR: u16 = 0xfffe;
+pub const IRQ_VECTOR: u16 = 0xfffe;
 
 pub const STACK_END: u16 = 0x100;
 
@@ -43,6 +44,7 @@ pub struct Cpu {
     sp: u8,
     x: u8,
     y: u8,
+    irq_inhibited: bool,
 }
 
 impl Cpu {
@@ -54,6 +56,7 @@ impl Cpu {
             sp: 0xfd,
             x: 0,
             y: 0,
+            irq_inhibited: false,
         }
     }
 
@@ -69,6 +72,25 @@ impl Cpu {
         self.pc = interconnect.read_double(0xfffa);
     }
 
+    /// Services an asserted IRQ line, returning the cycles spent. The interrupt disable
+    /// flag is the one sampled when the last instruction polled for interrupts, so the
+    /// flag changes made by CLI, SEI and PLP only take effect one instruction later.
+    pub fn irq(&mut self, interconnect: &mut Interconnect) -> u16 {
+        if self.irq_inhibited {
+            return 0;
+        }
+
+        let pc = self.pc;
+        self.push_double(interconnect, pc);
+        let p = self.p & !BREAK_COMMAND;
+        self.push_word(interconnect, p);
+        self.set_interrupt_disable(true);
+        self.irq_inhibited = true;
+        self.pc = interconnect.read_double(IRQ_VECTOR);
+
+        7
+    }
+
     pub fn step(&mut self, interconnect: &mut Interconnect) -> u16 {
         let opcode = self.read_pc(interconnect);
         let Instruction(op, am) = Instruction::from_opcode(opcode);
@@ -88,6 +110,7 @@ impl Cpu {
         }
 
         let mut dma_performed = false;
+        let interrupt_disable = self.interrupt_disable();
 
         match op {
             Op::Adc => with_value!(|value| self.adc(value)),
@@ -154,6 +177,11 @@ impl Cpu {
             Op::Txs => self.txs(),
         }
 
+        self.irq_inhibited = match op {
+            Op::Cli | Op::Sei | Op::Plp => interrupt_disable,
+            _ => self.interrupt_disable(),
+        };
+
         if dma_performed {
             512 + CYCLES[opcode as usize]
         } else {
@@ -1557,6 +1585,35 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_irq() {
+        let mut interconnect = TestInterconnect::new();
+        let mut cpu = Cpu::new();
+
+        interconnect.write_double(RESET_VECTOR, RESET_ADDR);
+        interconnect.write_double(IRQ_VECTOR, BREAK_ADDR);
+        let prg = [0x78 /* SEI */, 0xea /* NOP */, 0x58 /* CLI */, 0xea /* NOP */];
+        for (i, v) in prg.iter().enumerate() {
+            interconnect.write_word(RESET_ADDR + i as u16, *v);
+        }
+        cpu.reset(&mut interconnect);
+
+        cpu.step(&mut interconnect);
+        cpu.step(&mut interconnect);
+        assert_eq!(cpu.irq(&mut interconnect), 0);
+
+        cpu.step(&mut interconnect);
+        assert_eq!(cpu.irq(&mut interconnect), 0);
+
+        cpu.step(&mut interconnect);
+        assert_eq!(cpu.irq(&mut interconnect), 7);
+        assert_eq!(cpu.pc, BREAK_ADDR);
+        assert_eq!(cpu.p, INTERUPT_DISABLE);
+        assert_eq!(interconnect.read_double(STACK_END + 0xfc), RESET_ADDR + 4);
+        assert_eq!(interconnect.read_word(STACK_END + 0xfb), 0);
+        assert_eq!(cpu.irq(&mut interconnect), 0);
+    }
+
     #[test]
     fn test_sta() {
         test_prg!(vec![vec![0xa9, 0x01], // LDA #$01
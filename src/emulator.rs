@@ -1,24 +1,42 @@
+use std::fs::File;
+use std::io::BufWriter;
+
 use joypad::ButtonState;
 use nes::Nes;
-use rom::Rom;
+use save_file::SaveFile;
+use wav::WavWriter;
 use minifb::{Window, WindowOptions, Key};
 
+// Battery RAM is also written out every few seconds so a crash doesn't lose a save.
+const SAVE_INTERVAL_FRAMES: u32 = 300;
+
 pub struct Emulator {
     nes: Nes,
     window: Window,
+    recorder: Option<WavWriter<BufWriter<File>>>,
+    save_file: SaveFile,
 }
 
 impl Emulator {
-    pub fn new(rom: Rom) -> Emulator {
+    pub fn new(nes: Nes,
+               recorder: Option<WavWriter<BufWriter<File>>>,
+               save_file: SaveFile)
+               -> Emulator {
         Emulator {
-            nes: Nes::new(rom),
+            nes: nes,
             window: Window::new("NES", 256, 240, WindowOptions::default()).unwrap(),
+            recorder: recorder,
+            save_file: save_file,
         }
     }
 
     pub fn run(&mut self) {
+        if let Err(e) = self.save_file.load(&mut self.nes) {
+            eprintln!("warning: can't read {}: {}", self.save_file.path().display(), e);
+        }
         self.nes.reset();
 
+        let mut frames = 0u32;
         while self.window.is_open() {
             let joypad1_state = ButtonState {
                 a: self.window.is_key_down(Key::Z),
@@ -33,6 +51,34 @@ impl Emulator {
 
             let frame = self.nes.run_frame(joypad1_state);
             self.window.update_with_buffer(frame);
+
+            // There is no audio output device yet, so samples are only kept when
+            // recording.
+            let samples = self.nes.take_audio_samples();
+            if let Some(e) = self.recorder.as_mut().and_then(|r| r.write_samples(&samples).err()) {
+                eprintln!("warning: can't write the audio recording, stopping it: {}", e);
+                self.recorder = None;
+            }
+
+            frames = frames.wrapping_add(1);
+            if frames % SAVE_INTERVAL_FRAMES == 0 {
+                self.save();
+            }
+        }
+
+        self.save();
+
+        if let Some(recorder) = self.recorder.take() {
+            if let Err(e) = recorder.finish() {
+                eprintln!("warning: can't finish the audio recording: {}", e);
+            }
+        }
+    }
+
+    // A failed save is reported but doesn't stop the game; the next one may succeed.
+    fn save(&mut self) {
+        if let Err(e) = self.save_file.save(&self.nes) {
+            eprintln!("warning: can't write {}: {}", self.save_file.path().display(), e);
         }
     }
 }
@@ -1,8 +1,26 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use apu::Apu;
 use joypad::Joypad;
 use mapper::Mapper;
+use mapper::axrom::Axrom;
+use mapper::cnrom::Cnrom;
+use mapper::color_dreams::ColorDreams;
+use mapper::fme7::Fme7;
+use mapper::gxrom::Gxrom;
+use mapper::mmc1::Mmc1;
+use mapper::mmc2::{self, Mmc2};
+use mapper::mmc3::Mmc3;
+use mapper::mmc5::Mmc5;
+use mapper::namco163::Namco163;
+use mapper::nrom::Nrom;
 use mapper::unrom::Unrom;
+use mapper::vrc4::Vrc4;
+use mapper::vrc6::Vrc6;
+use mapper::vrc7::Vrc7;
 use ppu::Ppu;
-use rom::Rom;
+use rom::{Rom, RomError, TRAINER_SIZE};
 
 pub trait Interconnect {
     fn read_double(&mut self, addr: u16) -> u16;
@@ -11,11 +29,20 @@ pub trait Interconnect {
     fn write_double(&mut self, addr: u16, value: u16);
 }
 
+// A DMC fetch halts the CPU for up to four cycles.
+const DMC_STALL_CYCLES: u16 = 4;
+// Where $7000 falls in PRG RAM mapped at $6000.
+const TRAINER_OFFSET: usize = 0x1000;
+
 pub struct MemoryMappingInterconnect {
-    mapper: Box<Mapper>,
+    mapper: Rc<RefCell<Mapper>>,
+    battery: bool,
     ram: [u8; 2048],
     pub ppu: Ppu,
+    pub apu: Apu,
     pub joypad1: Joypad,
+    last_read_addr: u16,
+    stall_cycles: u16,
 }
 
 enum MappedAddress {
@@ -41,6 +68,7 @@ enum MappedAddress {
     PapuTriangleFrequencyRegister1,
     PapuTriangleFrequencyRegister2,
     PapuNoiseControlRegister1,
+    PapuNoiseControlRegister2,
     PapuNoiseFrequencyRegister1,
     PapuNoiseFrequencyRegister2,
     PapuDeltaModulationControlRegister,
@@ -51,12 +79,15 @@ enum MappedAddress {
     PapuSoundVerticalClockSignalRegister,
     Joypad1,
     Joypad2,
+    Expansion,
+    PrgRam,
     PrgRom,
 }
 
 fn map_addr(addr: u16) -> MappedAddress {
     match addr {
         0x0000...0x1fff => MappedAddress::Ram(addr as usize % 2048),
+        0x6000...0x7fff => MappedAddress::PrgRam,
         0x8000...0xffff => MappedAddress::PrgRom,
         0x2000...0x3fff => {
             match (addr - 0x2000) % 8 {
@@ -84,6 +115,7 @@ fn map_addr(addr: u16) -> MappedAddress {
         0x400A => MappedAddress::PapuTriangleFrequencyRegister1,
         0x400B => MappedAddress::PapuTriangleFrequencyRegister2,
         0x400C => MappedAddress::PapuNoiseControlRegister1,
+        0x400D => MappedAddress::PapuNoiseControlRegister2,
         0x400E => MappedAddress::PapuNoiseFrequencyRegister1,
         0x400F => MappedAddress::PapuNoiseFrequencyRegister2,
         0x4010 => MappedAddress::PapuDeltaModulationControlRegister,
@@ -94,22 +126,128 @@ fn map_addr(addr: u16) -> MappedAddress {
         0x4015 => MappedAddress::PapuSoundVerticalClockSignalRegister,
         0x4016 => MappedAddress::Joypad1,
         0x4017 => MappedAddress::Joypad2,
+        0x4020...0x5fff => MappedAddress::Expansion,
         _ => panic!("Unmappable address: {:x}", addr),
     }
 }
 
 impl MemoryMappingInterconnect {
-    pub fn new(rom: Rom) -> MemoryMappingInterconnect {
-        let mapper = match rom.mapper {
-            2 => Unrom::new(rom),
-            _ => panic!("Unimplemented mapper"),
+    pub fn new(mut rom: Rom) -> Result<MemoryMappingInterconnect, RomError> {
+        if rom.prg_rom.iter().map(|bank| bank.len()).sum::<usize>() < 0x2000 {
+            return Err(RomError::MissingPrgRom);
+        }
+
+        // NES 2.0 submapper 2 marks the UNROM, CNROM and AxROM boards that have bus conflicts.
+        // Older headers can't tell, so those boards are assumed to be conflict-free. Color
+        // Dreams and GxROM have no submappers to ask, and their games write values that
+        // match ROM anyway, so they are always run without conflicts.
+        let bus_conflicts = matches!(rom.mapper, 2 | 3 | 7) && rom.submapper == 2;
+        let battery = rom.battery;
+        let rom_mapper = rom.mapper;
+        let trainer = rom.trainer.take();
+
+        let mapper: Rc<RefCell<Mapper>> = match rom.mapper {
+            0 => Rc::new(RefCell::new(Nrom::new(rom))),
+            1 => Rc::new(RefCell::new(Mmc1::new(rom))),
+            2 => Rc::new(RefCell::new(Unrom::new(rom, bus_conflicts))),
+            3 => Rc::new(RefCell::new(Cnrom::new(rom, bus_conflicts))),
+            4 => Rc::new(RefCell::new(Mmc3::new(rom))),
+            5 => Rc::new(RefCell::new(Mmc5::new(rom))),
+            7 => Rc::new(RefCell::new(Axrom::new(rom, bus_conflicts))),
+            9 => Rc::new(RefCell::new(Mmc2::new(rom, mmc2::Revision::Mmc2))),
+            10 => Rc::new(RefCell::new(Mmc2::new(rom, mmc2::Revision::Mmc4))),
+            11 => Rc::new(RefCell::new(ColorDreams::new(rom, false))),
+            19 => Rc::new(RefCell::new(Namco163::new(rom))),
+            21 | 22 | 23 | 25 => Rc::new(RefCell::new(Vrc4::new(rom))),
+            24 | 26 => Rc::new(RefCell::new(Vrc6::new(rom))),
+            66 => Rc::new(RefCell::new(Gxrom::new(rom, false))),
+            69 => Rc::new(RefCell::new(Fme7::new(rom))),
+            85 => Rc::new(RefCell::new(Vrc7::new(rom))),
+            mapper => return Err(RomError::UnsupportedMapper(mapper)),
         };
 
-        MemoryMappingInterconnect {
-            mapper: Box::new(mapper),
+        // Trainers are copied into PRG RAM at $7000 as if the copier had just run. Boards
+        // without RAM reaching that far have nowhere to put one, so it is dropped with a
+        // warning and the game runs without it.
+        if let Some(trainer) = trainer {
+            match mapper.borrow_mut().prg_ram() {
+                Some(ref mut ram) if ram.len() >= TRAINER_OFFSET + TRAINER_SIZE => {
+                    ram[TRAINER_OFFSET..TRAINER_OFFSET + TRAINER_SIZE].copy_from_slice(&trainer);
+                }
+                _ => {
+                    eprintln!("warning: mapper {} has no PRG RAM at $7000, ignoring the trainer",
+                              rom_mapper)
+                }
+            }
+        }
+
+        Ok(MemoryMappingInterconnect {
+            ppu: Ppu::new(mapper.clone()),
+            mapper: mapper,
+            battery: battery,
             ram: [0; 2048],
-            ppu: Ppu::new(),
+            apu: Apu::new(),
             joypad1: Joypad::new(),
+            last_read_addr: 0,
+            stall_cycles: 0,
+        })
+    }
+}
+
+impl MemoryMappingInterconnect {
+    /// Whether any source is asserting the shared IRQ line.
+    pub fn irq(&self) -> bool {
+        self.apu.irq() || self.mapper.borrow().irq()
+    }
+
+    /// Tells the cartridge that the CPU has run for `cycles` cycles.
+    pub fn step_mapper(&mut self, cycles: u16) {
+        self.mapper.borrow_mut().cpu_cycles_elapsed(cycles);
+    }
+
+    /// Cycles the CPU was halted for by DMA since the last call.
+    pub fn take_stall_cycles(&mut self) -> u16 {
+        let cycles = self.stall_cycles;
+        self.stall_cycles = 0;
+        cycles
+    }
+
+    /// A copy of the cartridge's battery-backed RAM, or `None` if it has no battery.
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+
+        self.mapper.borrow_mut().prg_ram().map(|ram| ram.to_vec())
+    }
+
+    /// Restores the cartridge's battery-backed RAM from a previous `battery_ram`.
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        if !self.battery {
+            return;
+        }
+
+        if let Some(ram) = self.mapper.borrow_mut().prg_ram() {
+            let len = ram.len().min(data.len());
+            ram[..len].copy_from_slice(&data[..len]);
+        }
+    }
+
+    /// Advances the APU by one CPU cycle, servicing the DMC's sample fetches.
+    pub fn step_apu(&mut self) {
+        self.apu.expansion_audio = self.mapper.borrow_mut().step_audio();
+        self.apu.step();
+
+        if let Some(addr) = self.apu.dmc.dma_request() {
+            // The halted CPU repeats its last read while the DMC takes the bus, which
+            // clocks the controller shift register an extra time and drops a button.
+            if self.last_read_addr == 0x4016 {
+                self.joypad1.read();
+            }
+
+            let value = self.read_word(addr);
+            self.apu.dmc.fill_sample_buffer(value);
+            self.stall_cycles += DMC_STALL_CYCLES;
         }
     }
 }
@@ -120,21 +258,31 @@ impl Interconnect for MemoryMappingInterconnect {
     }
 
     fn read_word(&mut self, addr: u16) -> u8 {
+        self.last_read_addr = addr;
+
         match map_addr(addr) {
             MappedAddress::Ram(addr) => self.ram[addr],
-            MappedAddress::PrgRom => self.mapper.read(addr),
+            MappedAddress::PrgRam |
+            MappedAddress::PrgRom => self.mapper.borrow().read(addr),
             MappedAddress::PpuStatusRegister => self.ppu.read_status(),
             MappedAddress::VramIoRegister => self.ppu.read_vram_data(),
+            MappedAddress::PapuSoundVerticalClockSignalRegister => self.apu.read_status(),
             MappedAddress::Joypad1 => self.joypad1.read(),
             MappedAddress::Joypad2 => 0,
+            MappedAddress::Expansion => self.mapper.borrow_mut().expansion_read(addr),
             _ => panic!("Reading from unimplemented memory address: {:x}", addr),
         }
     }
 
     fn write_word(&mut self, addr: u16, value: u8) {
+        if let 0x2000...0x3fff = addr {
+            self.mapper.borrow_mut().ppu_register_write(addr, value);
+        }
+
         match map_addr(addr) {
             MappedAddress::Ram(addr) => self.ram[addr] = value,
-            MappedAddress::PrgRom => self.mapper.write(addr, value),
+            MappedAddress::PrgRam |
+            MappedAddress::PrgRom => self.mapper.borrow_mut().write(addr, value),
             MappedAddress::PpuControlRegister => self.ppu.write_ctrl(value),
             MappedAddress::PpuMaskRegister => self.ppu.write_mask(value),
             MappedAddress::SprRamAddressRegister => self.ppu.write_spr_ram_addr(value),
@@ -143,6 +291,48 @@ impl Interconnect for MemoryMappingInterconnect {
             MappedAddress::Joypad1 => self.joypad1.strobe(),
             MappedAddress::VramAddressRegister => self.ppu.write_vram_addr(value),
             MappedAddress::VramIoRegister => self.ppu.write_vram_data(value),
+            MappedAddress::PapuPulse1ControlRegister => self.apu.pulse1.write_control(value),
+            MappedAddress::PapuPulse1RampControlRegister => self.apu.pulse1.write_sweep(value),
+            MappedAddress::PapuPulse1FineTuneRegister => self.apu.pulse1.write_timer_low(value),
+            MappedAddress::PapuPulse1CoarseTuneRegister => {
+                self.apu.pulse1.write_timer_high(value)
+            }
+            MappedAddress::PapuPulse2ControlRegister => self.apu.pulse2.write_control(value),
+            MappedAddress::PapuPulse2RampControlRegister => self.apu.pulse2.write_sweep(value),
+            MappedAddress::PapuPulse2FineTuneRegister => self.apu.pulse2.write_timer_low(value),
+            MappedAddress::PapuPulse2CoarseTuneRegister => {
+                self.apu.pulse2.write_timer_high(value)
+            }
+            MappedAddress::PapuTriangleControlRegister1 => {
+                self.apu.triangle.write_linear_counter(value)
+            }
+            MappedAddress::PapuTriangleControlRegister2 => {}
+            MappedAddress::PapuTriangleFrequencyRegister1 => {
+                self.apu.triangle.write_timer_low(value)
+            }
+            MappedAddress::PapuTriangleFrequencyRegister2 => {
+                self.apu.triangle.write_timer_high(value)
+            }
+            MappedAddress::PapuNoiseControlRegister1 => self.apu.noise.write_control(value),
+            MappedAddress::PapuNoiseControlRegister2 => {}
+            MappedAddress::PapuNoiseFrequencyRegister1 => self.apu.noise.write_period(value),
+            MappedAddress::PapuNoiseFrequencyRegister2 => self.apu.noise.write_length(value),
+            MappedAddress::PapuDeltaModulationControlRegister => {
+                self.apu.dmc.write_control(value)
+            }
+            MappedAddress::PapuDeltaModulationDaRegister => {
+                self.apu.dmc.write_direct_load(value)
+            }
+            MappedAddress::PapuDeltaModulationAddressRegister => {
+                self.apu.dmc.write_sample_address(value)
+            }
+            MappedAddress::PapuDeltaModulationDataLengthRegister => {
+                self.apu.dmc.write_sample_length(value)
+            }
+            MappedAddress::PapuSoundVerticalClockSignalRegister => self.apu.write_status(value),
+            // $4017 is the second controller when read but the APU frame counter when written.
+            MappedAddress::Joypad2 => self.apu.write_frame_counter(value),
+            MappedAddress::Expansion => self.mapper.borrow_mut().expansion_write(addr, value),
             _ => {
                 println!("WARNING: Writing to unimplemented memory address: {:x}",
                          addr)
@@ -1,3 +1,4 @@
+#[derive(Default)]
 pub struct ButtonState {
     pub a: bool,
     pub b: bool,
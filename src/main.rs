@@ -1,5 +1,6 @@
 extern crate minifb;
 
+mod apu;
 mod cpu;
 mod emulator;
 mod interconnect;
@@ -8,12 +9,119 @@ mod mapper;
 mod nes;
 mod ppu;
 mod rom;
+mod save_file;
+mod wav;
 
 use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+
+use joypad::ButtonState;
+use nes::Nes;
+use save_file::SaveFile;
+use wav::WavWriter;
+
+const USAGE: &'static str = "usage: nes-rs <rom> [--record-audio <file.wav>] [--frames <count>]";
+
+struct Options {
+    rom_filename: String,
+    record_audio: Option<String>,
+    frames: Option<u32>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+    let mut rom_filename = None;
+    let mut record_audio = None;
+    let mut frames = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record-audio" => {
+                record_audio = Some(args.next().ok_or("--record-audio needs a file name")?);
+            }
+            "--frames" => {
+                let count = args.next().ok_or("--frames needs a count")?;
+                let count = count.parse()
+                    .map_err(|_| format!("Invalid frame count: {}", count))?;
+                frames = Some(count);
+            }
+            _ if rom_filename.is_none() => rom_filename = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(Options {
+        rom_filename: rom_filename.ok_or("No ROM given")?,
+        record_audio: record_audio,
+        frames: frames,
+    })
+}
+
+// Runs without opening a window, for machines with no display or sound card.
+fn run_headless(mut nes: Nes,
+                frames: u32,
+                mut recorder: Option<WavWriter<BufWriter<File>>>,
+                mut save_file: SaveFile) {
+    if let Err(e) = save_file.load(&mut nes) {
+        eprintln!("warning: can't read {}: {}", save_file.path().display(), e);
+    }
+    nes.reset();
+
+    for _ in 0..frames {
+        nes.run_frame(ButtonState::default());
+
+        let samples = nes.take_audio_samples();
+        if let Some(e) = recorder.as_mut().and_then(|r| r.write_samples(&samples).err()) {
+            eprintln!("warning: can't write the audio recording, stopping it: {}", e);
+            recorder = None;
+        }
+    }
+
+    if let Err(e) = save_file.save(&nes) {
+        eprintln!("warning: can't write {}: {}", save_file.path().display(), e);
+    }
+
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.finish() {
+            eprintln!("warning: can't finish the audio recording: {}", e);
+        }
+    }
+}
 
 fn main() {
-    let rom_filename = env::args().nth(1).unwrap();
-    let rom = rom::Rom::load(rom_filename).unwrap();
-    let mut emulator = emulator::Emulator::new(rom);
-    emulator.run();
+    let options = parse_args().unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, USAGE);
+        process::exit(1);
+    });
+
+    let save_file = SaveFile::for_rom(&options.rom_filename);
+    let rom = rom::Rom::load(&options.rom_filename).unwrap_or_else(|e| {
+        eprintln!("Can't load {}: {}", options.rom_filename, e);
+        process::exit(1);
+    });
+    if rom.timing != rom::Timing::Ntsc {
+        eprintln!("warning: {:?} timing isn't emulated, running at NTSC speed", rom.timing);
+    }
+    let nes = Nes::new(rom).unwrap_or_else(|e| {
+        eprintln!("Can't run {}: {}", options.rom_filename, e);
+        process::exit(1);
+    });
+    let recorder = options.record_audio.map(|filename| {
+        File::create(&filename)
+            .and_then(|file| WavWriter::new(BufWriter::new(file), apu::SAMPLE_RATE))
+            .unwrap_or_else(|e| {
+                eprintln!("Can't record to {}: {}", filename, e);
+                process::exit(1);
+            })
+    });
+
+    match options.frames {
+        Some(frames) => run_headless(nes, frames, recorder, save_file),
+        None => {
+            let mut emulator = emulator::Emulator::new(nes, recorder, save_file);
+            emulator.run();
+        }
+    }
 }
@@ -0,0 +1,89 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const PRG_BANK_SIZE: usize = 32768;
+
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom, bus_conflicts: bool) -> Axrom {
+        Axrom {
+            prg_rom: rom.prg_rom.concat(),
+            chr: Chr::new(&rom),
+            bus_conflicts: bus_conflicts,
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => {
+                let offset = self.prg_bank * PRG_BANK_SIZE + (addr - 0x8000) as usize;
+                self.prg_rom[offset % self.prg_rom.len()]
+            }
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {}
+            0x8000...0xffff => {
+                let value = if self.bus_conflicts {
+                    value & self.read(addr)
+                } else {
+                    value
+                };
+
+                self.prg_bank = (value & 0x07) as usize;
+                self.mirroring = if value & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                };
+            }
+            _ => panic!("AxROM unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        self.chr.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+    use rom::Mirroring;
+
+    #[test]
+    fn test_banking_and_mirroring() {
+        let mut axrom = Axrom::new(test_rom(7), false);
+        assert_eq!(axrom.read(0x8000), 0);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+        axrom.write(0x8000, 0x12);
+        assert_eq!(axrom.read(0x8000), 4);
+        assert_eq!(axrom.read(0xc000), 5);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
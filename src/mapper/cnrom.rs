@@ -0,0 +1,93 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const CHR_BANK_SIZE: usize = 8192;
+
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    chr_bank: usize,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom, bus_conflicts: bool) -> Cnrom {
+        Cnrom {
+            prg_rom: rom.prg_rom.concat(),
+            chr: Chr::new(&rom),
+            mirroring: rom.mirroring,
+            bus_conflicts: bus_conflicts,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {}
+            0x8000...0xffff => {
+                let value = if self.bus_conflicts {
+                    value & self.read(addr)
+                } else {
+                    value
+                };
+                self.chr_bank = value as usize;
+            }
+            _ => panic!("CNROM unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank * CHR_BANK_SIZE + addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_bank * CHR_BANK_SIZE + addr as usize;
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    fn new_cnrom(bus_conflicts: bool) -> Cnrom {
+        Cnrom::new(test_rom(3), bus_conflicts)
+    }
+
+    #[test]
+    fn test_chr_bank_switching() {
+        let mut cnrom = new_cnrom(false);
+
+        assert_eq!(cnrom.chr_read(0x0000), 0);
+        cnrom.write(0x8000, 0x03);
+        assert_eq!(cnrom.chr_read(0x1fff), 3);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // Only the first 32K is mapped, so $8000 reads 0 and $C000 reads 1.
+        let mut cnrom = new_cnrom(true);
+
+        cnrom.write(0xc000, 0x03);
+        assert_eq!(cnrom.chr_read(0x0000), 1);
+
+        cnrom.write(0x8000, 0x03);
+        assert_eq!(cnrom.chr_read(0x0000), 0);
+    }
+}
@@ -0,0 +1,437 @@
+use apu::PULSE_STEP_LEVEL;
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+const PRG_RAM_SELECT: u8 = 0x40;
+const PRG_RAM_ENABLE: u8 = 0x80;
+const IRQ_ENABLE: u8 = 0x01;
+const IRQ_COUNTER_ENABLE: u8 = 0x80;
+
+// The 5B's tone and envelope generators advance once every 16 CPU cycles.
+const AUDIO_TICK_CYCLES: u8 = 16;
+const ENVELOPE_CONTINUE: u8 = 0x08;
+const ENVELOPE_ATTACK: u8 = 0x04;
+const ENVELOPE_ALTERNATE: u8 = 0x02;
+const ENVELOPE_HOLD: u8 = 0x01;
+const VOLUME_ENVELOPE: u8 = 0x10;
+// Each of the 32 output levels is 1.5dB apart, with the top one at 1.0.
+const LEVEL_STEP_DB: f32 = 1.5;
+const OUTPUT_SCALE: f32 = 15.0 * PULSE_STEP_LEVEL;
+
+/// The Sunsoft 5B's audio: a YM2149 variant with three square channels, which can each
+/// mix in a shared noise generator and take their volume from a shared envelope.
+struct Sunsoft5b {
+    address: u8,
+    tone_periods: [u16; 3],
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_period: u8,
+    noise_counter: u8,
+    noise_shift: u32,
+    mixer: u8,
+    volumes: [u8; 3],
+    envelope_period: u16,
+    envelope_counter: u16,
+    envelope_shape: u8,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+    envelope_level: u8,
+    prescaler: u8,
+    levels: [f32; 32],
+}
+
+impl Sunsoft5b {
+    fn new() -> Sunsoft5b {
+        let mut levels = [0f32; 32];
+        for (level, entry) in levels.iter_mut().enumerate().skip(1) {
+            *entry = 10f32.powf((level as f32 - 31.0) * LEVEL_STEP_DB / 20.0);
+        }
+
+        Sunsoft5b {
+            address: 0,
+            tone_periods: [0; 3],
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            mixer: 0,
+            volumes: [0; 3],
+            envelope_period: 0,
+            envelope_counter: 0,
+            envelope_shape: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: true,
+            envelope_level: 0,
+            prescaler: 0,
+            levels: levels,
+        }
+    }
+
+    fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    fn write_data(&mut self, value: u8) {
+        match self.address {
+            register @ 0x00...0x05 => {
+                let channel = (register / 2) as usize;
+                let period = self.tone_periods[channel];
+                self.tone_periods[channel] = if register % 2 == 0 {
+                    period & 0x0f00 | value as u16
+                } else {
+                    period & 0x00ff | ((value & 0x0f) as u16) << 8
+                };
+            }
+            0x06 => self.noise_period = value & 0x1f,
+            0x07 => self.mixer = value,
+            register @ 0x08...0x0a => self.volumes[(register - 0x08) as usize] = value & 0x1f,
+            0x0b => self.envelope_period = self.envelope_period & 0xff00 | value as u16,
+            0x0c => self.envelope_period = self.envelope_period & 0x00ff | (value as u16) << 8,
+            0x0d => {
+                self.envelope_shape = value & 0x0f;
+                self.envelope_step = 0;
+                self.envelope_counter = 0;
+                self.envelope_holding = false;
+                self.envelope_attack = value & ENVELOPE_ATTACK != 0;
+                self.update_envelope_level();
+            }
+            _ => {}
+        }
+    }
+
+    fn update_envelope_level(&mut self) {
+        self.envelope_level = if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        };
+    }
+
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            self.update_envelope_level();
+            return;
+        }
+
+        let shape = self.envelope_shape;
+        if shape & ENVELOPE_CONTINUE == 0 {
+            self.envelope_holding = true;
+            self.envelope_level = 0;
+        } else if shape & ENVELOPE_HOLD != 0 {
+            self.envelope_holding = true;
+            if shape & ENVELOPE_ALTERNATE != 0 {
+                self.envelope_attack = !self.envelope_attack;
+                self.update_envelope_level();
+            }
+        } else {
+            if shape & ENVELOPE_ALTERNATE != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+            self.update_envelope_level();
+        }
+    }
+
+    fn tick(&mut self) {
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_periods[channel] {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        // Noise runs at half the tone rate.
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period.max(1) * 2 {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 0x01;
+            self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+        }
+
+        self.envelope_counter += 1;
+        if self.envelope_counter >= self.envelope_period.max(1) {
+            self.envelope_counter = 0;
+            self.step_envelope();
+        }
+    }
+
+    fn step(&mut self) -> f32 {
+        self.prescaler += 1;
+        if self.prescaler == AUDIO_TICK_CYCLES {
+            self.prescaler = 0;
+            self.tick();
+        }
+
+        let noise = self.noise_shift & 0x01 != 0;
+        let mut output = 0.0;
+        for channel in 0..3 {
+            // The mixer bits are active low: a set bit takes the source out of the mix.
+            let tone = self.tone_outputs[channel] || self.mixer & (0x01 << channel) != 0;
+            let noise = noise || self.mixer & (0x08 << channel) != 0;
+            if !(tone && noise) {
+                continue;
+            }
+
+            let volume = self.volumes[channel];
+            let level = if volume & VOLUME_ENVELOPE != 0 {
+                self.envelope_level
+            } else if volume == 0 {
+                0
+            } else {
+                (volume & 0x0f) * 2 + 1
+            };
+            output += self.levels[level as usize];
+        }
+
+        output
+    }
+}
+
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    command: u8,
+    prg_ram_bank: u8,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    irq_control: u8,
+    irq_counter: u16,
+    irq: bool,
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(rom: Rom) -> Fme7 {
+        Fme7 {
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; 8192],
+            chr: Chr::new(&rom),
+            command: 0,
+            prg_ram_bank: 0,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring: rom.mirroring,
+            irq_control: 0,
+            irq_counter: 0,
+            irq: false,
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x6000...0x7fff => (self.prg_ram_bank & 0x3f) as usize,
+            0x8000...0xdfff => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize,
+            _ => self.prg_rom.len() / PRG_BANK_SIZE - 1,
+        };
+
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            command @ 0x00...0x07 => self.chr_banks[command as usize] = value,
+            0x08 => self.prg_ram_bank = value,
+            command @ 0x09...0x0b => self.prg_banks[(command - 0x09) as usize] = value & 0x3f,
+            0x0c => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0x0d => {
+                self.irq_control = value;
+                self.irq = false;
+            }
+            0x0e => self.irq_counter = self.irq_counter & 0xff00 | value as u16,
+            _ => self.irq_counter = self.irq_counter & 0x00ff | (value as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff if self.prg_ram_bank & PRG_RAM_SELECT != 0 => {
+                if self.prg_ram_bank & PRG_RAM_ENABLE != 0 {
+                    self.prg_ram[(addr - 0x6000) as usize]
+                } else {
+                    0
+                }
+            }
+            0x6000...0xffff => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {
+                if self.prg_ram_bank & (PRG_RAM_SELECT | PRG_RAM_ENABLE) ==
+                   PRG_RAM_SELECT | PRG_RAM_ENABLE {
+                    self.prg_ram[(addr - 0x6000) as usize] = value;
+                }
+            }
+            0x8000...0x9fff => self.command = value & 0x0f,
+            0xa000...0xbfff => self.write_parameter(value),
+            0xc000...0xdfff => self.audio.write_address(value),
+            0xe000...0xffff => self.audio.write_data(value),
+            _ => panic!("FME-7 unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_cycles_elapsed(&mut self, cycles: u16) {
+        if self.irq_control & IRQ_COUNTER_ENABLE == 0 {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xffff && self.irq_control & IRQ_ENABLE != 0 {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn step_audio(&mut self) -> f32 {
+        self.audio.step() * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    fn new_fme7() -> Fme7 {
+        Fme7::new(test_rom(69))
+    }
+
+    fn command(fme7: &mut Fme7, command: u8, parameter: u8) {
+        fme7.write(0x8000, command);
+        fme7.write(0xa000, parameter);
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut fme7 = new_fme7();
+        command(&mut fme7, 0x09, 0x02);
+        command(&mut fme7, 0x0b, 0x0c);
+        command(&mut fme7, 0x07, 0x18);
+        assert_eq!(fme7.read(0x8000), 1);
+        assert_eq!(fme7.read(0xc000), 6);
+        assert_eq!(fme7.read(0xe000), 7);
+        assert_eq!(fme7.chr_read(0x1c00), 3);
+
+        command(&mut fme7, 0x08, 0x04);
+        assert_eq!(fme7.read(0x6000), 2);
+
+        command(&mut fme7, 0x08, PRG_RAM_SELECT | PRG_RAM_ENABLE);
+        fme7.write(0x6000, 0xaa);
+        assert_eq!(fme7.read(0x6000), 0xaa);
+        command(&mut fme7, 0x08, PRG_RAM_SELECT);
+        assert_eq!(fme7.read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut fme7 = new_fme7();
+        command(&mut fme7, 0x0e, 0x02);
+        command(&mut fme7, 0x0f, 0x00);
+        command(&mut fme7, 0x0d, IRQ_ENABLE | IRQ_COUNTER_ENABLE);
+
+        fme7.cpu_cycles_elapsed(2);
+        assert!(!fme7.irq());
+        fme7.cpu_cycles_elapsed(1);
+        assert!(fme7.irq());
+
+        command(&mut fme7, 0x0d, IRQ_COUNTER_ENABLE);
+        assert!(!fme7.irq());
+    }
+
+    #[test]
+    fn test_envelope_shapes() {
+        let mut audio = Sunsoft5b::new();
+        audio.write_address(0x0d);
+
+        // Attack then hold at the top.
+        audio.write_data(ENVELOPE_CONTINUE | ENVELOPE_ATTACK | ENVELOPE_HOLD);
+        for _ in 0..40 {
+            audio.step_envelope();
+        }
+        assert_eq!(audio.envelope_level, 31);
+
+        // Decay and drop to silence.
+        audio.write_data(0x00);
+        assert_eq!(audio.envelope_level, 31);
+        for _ in 0..40 {
+            audio.step_envelope();
+        }
+        assert_eq!(audio.envelope_level, 0);
+
+        // Triangle.
+        audio.write_data(ENVELOPE_CONTINUE | ENVELOPE_ALTERNATE);
+        for _ in 0..32 {
+            audio.step_envelope();
+        }
+        assert_eq!(audio.envelope_level, 0);
+        audio.step_envelope();
+        assert_eq!(audio.envelope_level, 1);
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut fme7 = new_fme7();
+        for &(register, value) in [(0x00, 0x01), (0x07, 0x3e), (0x08, 0x0f)].iter() {
+            fme7.write(0xc000, register);
+            fme7.write(0xe000, value);
+        }
+
+        // A period of one toggles the square every 16 CPU cycles.
+        let samples: Vec<f32> = (0..64).map(|_| fme7.step_audio()).collect();
+        assert_eq!(samples[15], OUTPUT_SCALE);
+        assert_eq!(samples[31], 0.0);
+        assert_eq!(samples[47], OUTPUT_SCALE);
+    }
+}
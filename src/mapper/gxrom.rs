@@ -0,0 +1,86 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const PRG_BANK_SIZE: usize = 32768;
+const CHR_BANK_SIZE: usize = 8192;
+
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl Gxrom {
+    pub fn new(rom: Rom, bus_conflicts: bool) -> Gxrom {
+        Gxrom {
+            prg_rom: rom.prg_rom.concat(),
+            chr: Chr::new(&rom),
+            mirroring: rom.mirroring,
+            bus_conflicts: bus_conflicts,
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Gxrom {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => {
+                let offset = self.prg_bank * PRG_BANK_SIZE + (addr - 0x8000) as usize;
+                self.prg_rom[offset % self.prg_rom.len()]
+            }
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {}
+            0x8000...0xffff => {
+                let value = if self.bus_conflicts {
+                    value & self.read(addr)
+                } else {
+                    value
+                };
+
+                self.prg_bank = ((value >> 4) & 0x03) as usize;
+                self.chr_bank = (value & 0x03) as usize;
+            }
+            _ => panic!("GxROM unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank * CHR_BANK_SIZE + addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_bank * CHR_BANK_SIZE + addr as usize;
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    #[test]
+    fn test_banking() {
+        let mut gxrom = Gxrom::new(test_rom(66), false);
+        gxrom.write(0x8000, 0x23);
+        assert_eq!(gxrom.read(0x8000), 4);
+        assert_eq!(gxrom.read(0xffff), 5);
+        assert_eq!(gxrom.chr_read(0x0000), 3);
+        assert_eq!(gxrom.chr_read(0x1fff), 3);
+    }
+}
@@ -0,0 +1,234 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const PRG_BANK_SIZE: usize = 16384;
+const PRG_RAM_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 4096;
+const SHIFT_REGISTER_RESET: u8 = 0x10;
+const CONTROL_CHR_4K_MODE: u8 = 0x10;
+const PRG_RAM_DISABLE: u8 = 0x10;
+
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    shift_register: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Mmc1 {
+        // Older headers can't tell SXROM's 32 KiB of banked PRG RAM from the single 8 KiB
+        // bank on every other board, SUROM included, so only NES 2.0 images get the banks.
+        let prg_ram_size = rom.prg_ram_size_or(PRG_RAM_BANK_SIZE);
+
+        Mmc1 {
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; prg_ram_size],
+            chr: Chr::new(&rom),
+            shift_register: SHIFT_REGISTER_RESET,
+            control: 0x0c,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000...0x9fff => self.control = value,
+            0xa000...0xbfff => self.chr_bank_0 = value,
+            0xc000...0xdfff => self.chr_bank_1 = value,
+            0xe000...0xffff => self.prg_bank = value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        // SUROM and SXROM use bit 4 of the CHR bank register to pick a 256 KiB half.
+        let outer = (self.chr_bank_0 & 0x10) as usize;
+        let bank = (self.prg_bank & 0x0f) as usize;
+
+        let bank = match ((self.control >> 2) & 0x03, addr) {
+            (0, 0x8000...0xbfff) | (1, 0x8000...0xbfff) => bank & !0x01,
+            (0, _) | (1, _) => bank | 0x01,
+            (2, 0x8000...0xbfff) => 0,
+            (2, _) => bank,
+            (_, 0x8000...0xbfff) => bank,
+            (_, _) => 0x0f,
+        };
+
+        (outer | bank) * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn prg_ram_offset(&self, addr: u16) -> usize {
+        let bank = ((self.chr_bank_0 >> 2) & 0x03) as usize;
+        (bank * PRG_RAM_BANK_SIZE + (addr - 0x6000) as usize) % self.prg_ram.len()
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & PRG_RAM_DISABLE == 0
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = if self.control & CONTROL_CHR_4K_MODE == 0 {
+            (self.chr_bank_0 & !0x01) as usize + (addr as usize / CHR_BANK_SIZE)
+        } else if addr < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        };
+
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff if self.prg_ram_enabled() => self.prg_ram[self.prg_ram_offset(addr)],
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => self.prg_rom[self.prg_rom_offset(addr) % self.prg_rom.len()],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {
+                if self.prg_ram_enabled() {
+                    let offset = self.prg_ram_offset(addr);
+                    self.prg_ram[offset] = value;
+                }
+            }
+            0x8000...0xffff => {
+                if value & 0x80 != 0 {
+                    self.shift_register = SHIFT_REGISTER_RESET;
+                    self.control |= 0x0c;
+                    return;
+                }
+
+                // The register is full once the marker bit reaches bit 0.
+                let full = self.shift_register & 0x01 != 0;
+                self.shift_register = (self.shift_register >> 1) | (value & 0x01) << 4;
+
+                if full {
+                    let value = self.shift_register;
+                    self.write_register(addr, value);
+                    self.shift_register = SHIFT_REGISTER_RESET;
+                }
+            }
+            _ => panic!("MMC1 unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+    use rom::{Mirroring, Rom};
+
+    // The test cartridge with its PRG ROM widened to `prg_banks` banks, for SUROM.
+    fn new_mmc1(prg_banks: usize) -> Mmc1 {
+        Mmc1::new(Rom {
+            prg_rom: (0..prg_banks).map(|bank| vec![bank as u8; PRG_BANK_SIZE]).collect(),
+            ..test_rom(1)
+        })
+    }
+
+    fn write_serial(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mmc1.write(addr, value >> i);
+        }
+    }
+
+    #[test]
+    fn test_serial_load() {
+        let mut mmc1 = new_mmc1(8);
+
+        assert_eq!(mmc1.read(0x8000), 0);
+        assert_eq!(mmc1.read(0xc000), 7);
+
+        write_serial(&mut mmc1, 0xe000, 0x03);
+        assert_eq!(mmc1.read(0x8000), 3);
+
+        mmc1.write(0xe000, 0x01);
+        mmc1.write(0xe000, 0x80);
+        write_serial(&mut mmc1, 0xe000, 0x05);
+        assert_eq!(mmc1.read(0x8000), 5);
+    }
+
+    #[test]
+    fn test_prg_modes_and_mirroring() {
+        let mut mmc1 = new_mmc1(8);
+
+        write_serial(&mut mmc1, 0xe000, 0x03);
+        write_serial(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(mmc1.read(0x8000), 2);
+        assert_eq!(mmc1.read(0xc000), 3);
+        assert_eq!(mmc1.mirroring(), Mirroring::SingleScreenLower);
+
+        write_serial(&mut mmc1, 0x8000, 0x0a);
+        assert_eq!(mmc1.read(0x8000), 0);
+        assert_eq!(mmc1.read(0xc000), 3);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_surom_outer_bank() {
+        let mut mmc1 = new_mmc1(32);
+
+        write_serial(&mut mmc1, 0xa000, 0x10);
+        write_serial(&mut mmc1, 0xe000, 0x02);
+        assert_eq!(mmc1.read(0x8000), 0x12);
+        assert_eq!(mmc1.read(0xc000), 0x1f);
+
+        write_serial(&mut mmc1, 0xa000, 0x00);
+        assert_eq!(mmc1.read(0xc000), 0x0f);
+    }
+
+    #[test]
+    fn test_prg_ram_banks() {
+        // A 512 KiB iNES 1.0 image gets SUROM's single bank, which the CHR lines can't switch.
+        let mut surom = new_mmc1(32);
+        surom.write(0x6000, 0x42);
+        write_serial(&mut surom, 0xa000, 0x0c);
+        assert_eq!(surom.read(0x6000), 0x42);
+
+        let mut sxrom = Mmc1::new(Rom {
+            prg_nvram_size: 4 * PRG_RAM_BANK_SIZE,
+            ..test_rom(1)
+        });
+        sxrom.write(0x6000, 0x42);
+        write_serial(&mut sxrom, 0xa000, 0x0c);
+        assert_eq!(sxrom.read(0x6000), 0);
+        write_serial(&mut sxrom, 0xa000, 0x00);
+        assert_eq!(sxrom.read(0x6000), 0x42);
+    }
+}
@@ -0,0 +1,214 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const CHR_BANK_SIZE: usize = 4096;
+const LATCH_FD: u8 = 0xfd;
+const LATCH_FE: u8 = 0xfe;
+
+/// MMC4 is an MMC2 with 16 KiB PRG banking, PRG RAM, and a wider trigger range for the
+/// left pattern table latch.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Revision {
+    Mmc2,
+    Mmc4,
+}
+
+pub struct Mmc2 {
+    revision: Revision,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    prg_bank: u8,
+    // Indexed by pattern table, then by latch: the $FD bank first, the $FE bank second.
+    chr_banks: [[u8; 2]; 2],
+    latches: [u8; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(rom: Rom, revision: Revision) -> Mmc2 {
+        Mmc2 {
+            revision: revision,
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: match revision {
+                Revision::Mmc2 => Vec::new(),
+                Revision::Mmc4 => vec![0; 8192],
+            },
+            chr: Chr::new(&rom),
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [LATCH_FE; 2],
+            mirroring: rom.mirroring,
+        }
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        match self.revision {
+            Revision::Mmc2 => 8192,
+            Revision::Mmc4 => 16384,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let bank_size = self.prg_bank_size();
+        let banks = self.prg_rom.len() / bank_size;
+        let slot = (addr - 0x8000) as usize / bank_size;
+
+        // Everything above the switchable slot is fixed to the end of PRG ROM.
+        let bank = if slot == 0 {
+            self.prg_bank as usize
+        } else {
+            banks - (0x8000 / bank_size) + slot
+        };
+
+        (bank * bank_size + (addr as usize & (bank_size - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let table = (addr >> 12) as usize & 0x01;
+        let latch = (self.latches[table] - LATCH_FD) as usize;
+        let bank = self.chr_banks[table][latch] as usize;
+
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff if self.revision == Revision::Mmc4 => {
+                self.prg_ram[(addr - 0x6000) as usize]
+            }
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {
+                if self.revision == Revision::Mmc4 {
+                    self.prg_ram[(addr - 0x6000) as usize] = value;
+                }
+            }
+            0x8000...0x9fff => {}
+            0xa000...0xafff => self.prg_bank = value & 0x0f,
+            0xb000...0xbfff => self.chr_banks[0][0] = value & 0x1f,
+            0xc000...0xcfff => self.chr_banks[0][1] = value & 0x1f,
+            0xd000...0xdfff => self.chr_banks[1][0] = value & 0x1f,
+            0xe000...0xefff => self.chr_banks[1][1] = value & 0x1f,
+            0xf000...0xffff => {
+                self.mirroring = if value & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            _ => panic!("MMC2 unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // Only MMC4 boards have RAM at $6000.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        match self.revision {
+            Revision::Mmc2 => None,
+            Revision::Mmc4 => Some(&mut self.prg_ram),
+        }
+    }
+
+    // The latches flip after the fetch of the high plane of tile $FD or $FE, so the
+    // tile that triggers the switch is still drawn from the old bank. MMC2 only
+    // watches the first row of that tile in the left pattern table.
+    fn ppu_bus_access(&mut self, addr: u16) {
+        let left_range = match self.revision {
+            Revision::Mmc2 => 0,
+            Revision::Mmc4 => 7,
+        };
+
+        match addr {
+            0x0fd8...0x0fdf if addr - 0x0fd8 <= left_range => self.latches[0] = LATCH_FD,
+            0x0fe8...0x0fef if addr - 0x0fe8 <= left_range => self.latches[0] = LATCH_FE,
+            0x1fd8...0x1fdf => self.latches[1] = LATCH_FD,
+            0x1fe8...0x1fef => self.latches[1] = LATCH_FE,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    fn new_mmc2(revision: Revision) -> Mmc2 {
+        Mmc2::new(test_rom(9), revision)
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mmc2 = new_mmc2(Revision::Mmc2);
+        mmc2.write(0xa000, 0x03);
+        assert_eq!(mmc2.read(0x8000), 1);
+        assert_eq!(mmc2.read(0xa000), 6);
+        assert_eq!(mmc2.read(0xffff), 7);
+
+        let mut mmc4 = new_mmc2(Revision::Mmc4);
+        mmc4.write(0xa000, 0x03);
+        assert_eq!(mmc4.read(0x8000), 3);
+        assert_eq!(mmc4.read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_chr_latches() {
+        let mut mmc2 = new_mmc2(Revision::Mmc2);
+        mmc2.write(0xb000, 0x02);
+        mmc2.write(0xc000, 0x04);
+        mmc2.write(0xd000, 0x06);
+        mmc2.write(0xe000, 0x08);
+
+        assert_eq!(mmc2.chr_read(0x0000), 2);
+        assert_eq!(mmc2.chr_read(0x1000), 4);
+
+        mmc2.ppu_bus_access(0x0fd8);
+        mmc2.ppu_bus_access(0x1fdc);
+        assert_eq!(mmc2.chr_read(0x0000), 1);
+        assert_eq!(mmc2.chr_read(0x1000), 3);
+
+        // Only the first row of the left table's tiles trips the MMC2 latch.
+        mmc2.ppu_bus_access(0x0fe9);
+        assert_eq!(mmc2.chr_read(0x0000), 1);
+        mmc2.ppu_bus_access(0x0fe8);
+        assert_eq!(mmc2.chr_read(0x0000), 2);
+
+        let mut mmc4 = new_mmc2(Revision::Mmc4);
+        mmc4.write(0xb000, 0x02);
+        mmc4.ppu_bus_access(0x0fdf);
+        assert_eq!(mmc4.chr_read(0x0000), 1);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mmc2 = new_mmc2(Revision::Mmc2);
+        assert!(mmc2.prg_ram().is_none());
+        mmc2.write(0x6000, 0x42);
+        assert_eq!(mmc2.read(0x6000), 0);
+
+        let mut mmc4 = new_mmc2(Revision::Mmc4);
+        mmc4.write(0x6000, 0x42);
+        assert_eq!(mmc4.prg_ram().unwrap()[0], 0x42);
+    }
+}
@@ -0,0 +1,241 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+const BANK_SELECT_PRG_MODE: u8 = 0x40;
+const BANK_SELECT_CHR_INVERSION: u8 = 0x80;
+const PRG_RAM_WRITE_PROTECT: u8 = 0x40;
+const PRG_RAM_ENABLE: u8 = 0x80;
+// A12 has to stay low for a few fetches before a rise counts, which filters out the
+// toggling between nametable and pattern fetches within a single tile.
+const A12_LOW_FETCHES: u8 = 3;
+
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    four_screen: bool,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    a12_low_fetches: u8,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Mmc3 {
+        Mmc3 {
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; 8192],
+            chr: Chr::new(&rom),
+            four_screen: rom.mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.mirroring,
+            prg_ram_protect: PRG_RAM_ENABLE,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            a12_low_fetches: 0,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let last_bank = self.prg_rom.len() / PRG_BANK_SIZE - 1;
+        let swapped = self.bank_select & BANK_SELECT_PRG_MODE != 0;
+
+        let bank = match (addr, swapped) {
+            (0x8000...0x9fff, false) | (0xc000...0xdfff, true) => self.banks[6] as usize,
+            (0x8000...0x9fff, true) | (0xc000...0xdfff, false) => last_bank - 1,
+            (0xa000...0xbfff, _) => self.banks[7] as usize,
+            _ => last_bank,
+        };
+
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & BANK_SELECT_CHR_INVERSION != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+
+        let bank = match addr {
+            0x0000...0x07ff => (self.banks[0] & 0xfe) as usize + (addr as usize >> 10 & 0x01),
+            0x0800...0x0fff => (self.banks[1] & 0xfe) as usize + (addr as usize >> 10 & 0x01),
+            _ => self.banks[2 + (addr as usize - 0x1000) / CHR_BANK_SIZE] as usize,
+        };
+
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff if self.prg_ram_protect & PRG_RAM_ENABLE != 0 => {
+                self.prg_ram[(addr - 0x6000) as usize]
+            }
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match (addr, addr & 0x01) {
+            (0x6000...0x7fff, _) => {
+                if self.prg_ram_protect & (PRG_RAM_ENABLE | PRG_RAM_WRITE_PROTECT) ==
+                   PRG_RAM_ENABLE {
+                    self.prg_ram[(addr - 0x6000) as usize] = value;
+                }
+            }
+            (0x8000...0x9fff, 0) => self.bank_select = value,
+            (0x8000...0x9fff, _) => self.banks[(self.bank_select & 0x07) as usize] = value,
+            (0xa000...0xbfff, 0) => {
+                if !self.four_screen {
+                    self.mirroring = if value & 0x01 == 0 {
+                        Mirroring::Vertical
+                    } else {
+                        Mirroring::Horizontal
+                    };
+                }
+            }
+            (0xa000...0xbfff, _) => self.prg_ram_protect = value,
+            (0xc000...0xdfff, 0) => self.irq_latch = value,
+            (0xc000...0xdfff, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xe000...0xffff, 0) => {
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            (0xe000...0xffff, _) => self.irq_enabled = true,
+            _ => panic!("MMC3 unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn ppu_bus_access(&mut self, addr: u16) {
+        if addr & 0x1000 == 0 {
+            self.a12_low_fetches = self.a12_low_fetches.saturating_add(1);
+            return;
+        }
+
+        if self.a12_low_fetches >= A12_LOW_FETCHES {
+            self.clock_irq_counter();
+        }
+        self.a12_low_fetches = 0;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+    use rom::Mirroring;
+
+    fn new_mmc3() -> Mmc3 {
+        Mmc3::new(test_rom(4))
+    }
+
+    fn scanline(mmc3: &mut Mmc3) {
+        for _ in 0..8 {
+            mmc3.ppu_bus_access(0x2000);
+        }
+        mmc3.ppu_bus_access(0x1000);
+        mmc3.ppu_bus_access(0x1008);
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        let mut mmc3 = new_mmc3();
+
+        // The test cartridge's banks are 16K of PRG and 8K of CHR, so an 8K PRG bank
+        // reads as half its number and a 1K CHR bank as an eighth.
+        mmc3.write(0x8000, 0x06);
+        mmc3.write(0x8001, 0x04);
+        mmc3.write(0x8000, 0x00);
+        mmc3.write(0x8001, 0x17);
+        mmc3.write(0x8000, 0x05);
+        mmc3.write(0x8001, 0x30);
+
+        assert_eq!(mmc3.read(0x8000), 2);
+        assert_eq!(mmc3.read(0xc000), 7);
+        assert_eq!(mmc3.read(0xe000), 7);
+        assert_eq!(mmc3.chr_read(0x0000), 2);
+        assert_eq!(mmc3.chr_read(0x0400), 2);
+        assert_eq!(mmc3.chr_read(0x1c00), 6);
+
+        mmc3.write(0x8000, BANK_SELECT_PRG_MODE | BANK_SELECT_CHR_INVERSION);
+        assert_eq!(mmc3.read(0x8000), 7);
+        assert_eq!(mmc3.read(0xc000), 2);
+        assert_eq!(mmc3.chr_read(0x1000), 2);
+        assert_eq!(mmc3.chr_read(0x0c00), 6);
+
+        mmc3.write(0xa000, 0x01);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc3 = new_mmc3();
+
+        mmc3.write(0xc000, 2);
+        mmc3.write(0xc001, 0);
+        mmc3.write(0xe001, 0);
+
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+
+        mmc3.write(0xe000, 0);
+        assert!(!mmc3.irq());
+    }
+}
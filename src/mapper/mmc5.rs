@@ -0,0 +1,657 @@
+use apu::PULSE_STEP_LEVEL;
+use apu::pulse::{Pulse, PulseChannel};
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const PRG_BANK_SIZE: usize = 8192;
+const PRG_RAM_SIZE: usize = 65536;
+const EXRAM_SIZE: usize = 1024;
+const PRG_BANK_ROM: u8 = 0x80;
+const CTRL_SPRITE_SIZE_FLAG: u8 = 0x20;
+const MASK_RENDERING_FLAGS: u8 = 0x18;
+const SPLIT_ENABLE: u8 = 0x80;
+const SPLIT_RIGHT: u8 = 0x40;
+const IRQ_ENABLE: u8 = 0x80;
+const IRQ_PENDING: u8 = 0x80;
+const IRQ_IN_FRAME: u8 = 0x40;
+const AUDIO_STATUS_PULSE1: u8 = 0x01;
+const AUDIO_STATUS_PULSE2: u8 = 0x02;
+// The pulses' envelopes and length counters run off an internal 240Hz timer rather than
+// the APU's frame counter.
+const AUDIO_FRAME_CYCLES: u32 = 7457;
+// The pulses are the APU's. A full scale PCM sample swings as far as the DMC's full
+// range, about 57 pulse steps.
+const PULSE_OUTPUT_SCALE: f32 = PULSE_STEP_LEVEL;
+const PCM_OUTPUT_SCALE: f32 = PULSE_STEP_LEVEL * 57.0 / 255.0;
+
+// After the scanline is detected the PPU makes 32 background tile fetches of four reads
+// each, 16 sprite pattern reads, then fetches the first two tiles of the next line.
+const BACKGROUND_FETCHES: usize = 128;
+const SPRITE_FETCHES: usize = 16;
+const PREFETCHES: usize = 8;
+
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    exram: [u8; EXRAM_SIZE],
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    name_table_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117: the PRG RAM bank at $6000 followed by the four PRG windows.
+    prg_banks: [u8; 5],
+    chr_banks_a: [u16; 8],
+    chr_banks_b: [u16; 4],
+    chr_upper: u16,
+    last_chr_write_b: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    sprites_8x16: bool,
+    in_frame: bool,
+    scanline: usize,
+    fetches: usize,
+    last_read_addr: u16,
+    matching_reads: u8,
+    ext_attribute: u8,
+    split_tile: bool,
+    split_y: usize,
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm: u8,
+    audio_cycle: u32,
+}
+
+impl Mmc5 {
+    pub fn new(rom: Rom) -> Mmc5 {
+        Mmc5 {
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; rom.prg_ram_size_or(PRG_RAM_SIZE)],
+            chr: Chr::new(&rom),
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 3,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            name_table_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xff],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper: 0,
+            last_chr_write_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            sprites_8x16: false,
+            in_frame: false,
+            scanline: 0,
+            fetches: 0,
+            last_read_addr: 0,
+            matching_reads: 0,
+            ext_attribute: 0,
+            split_tile: false,
+            split_y: 0,
+            pulse1: Pulse::new(PulseChannel::Mmc5),
+            pulse2: Pulse::new(PulseChannel::Mmc5),
+            pcm: 0,
+            audio_cycle: 0,
+        }
+    }
+
+    /// Resolves a CPU address to an offset into PRG ROM or, when the window holds RAM, an
+    /// offset into PRG RAM.
+    fn prg_offset(&self, addr: u16) -> Result<usize, usize> {
+        let (register, banks) = match (self.prg_mode, addr) {
+            (_, 0x6000...0x7fff) => (0, 1),
+            (0, _) => (4, 4),
+            (1, 0x8000...0xbfff) | (2, 0x8000...0xbfff) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0xc000...0xdfff) => (3, 1),
+            (2, _) => (4, 1),
+            _ => ((addr as usize - 0x6000) / PRG_BANK_SIZE, 1),
+        };
+
+        let value = self.prg_banks[register];
+        let bank = (value & 0x7f) as usize & !(banks - 1) |
+                   ((addr as usize & 0x7fff) / PRG_BANK_SIZE) % banks;
+        let offset = bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+
+        if register == 4 || (register > 0 && value & PRG_BANK_ROM != 0) {
+            Ok(offset % self.prg_rom.len())
+        } else {
+            Err(offset % self.prg_ram.len())
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    fn chr_offset(&self, addr: u16, sprite: bool) -> usize {
+        // Set B is only split off for backgrounds while rendering. Outside it, $2007 goes
+        // through whichever set was written last.
+        let use_b = if self.sprites_8x16 && self.in_frame {
+            !sprite
+        } else {
+            self.last_chr_write_b
+        };
+
+        // Set B only covers 4K, which shows up in both pattern tables.
+        let addr = if use_b && self.chr_mode != 0 {
+            addr as usize & 0x0fff
+        } else {
+            addr as usize
+        };
+
+        let size = 0x2000 >> self.chr_mode;
+        let step = 8 >> self.chr_mode;
+        let register = addr / size * step + step - 1;
+        let bank = if use_b {
+            self.chr_banks_b[register & 0x03]
+        } else {
+            self.chr_banks_a[register]
+        };
+
+        bank as usize * size + (addr & (size - 1))
+    }
+
+    /// The tile column a background fetch belongs to, or `None` if the read is a sprite
+    /// or dummy fetch.
+    fn background_column(fetch: usize) -> Option<usize> {
+        if fetch < BACKGROUND_FETCHES {
+            Some(fetch / 4 + 2)
+        } else if fetch >= BACKGROUND_FETCHES + SPRITE_FETCHES &&
+                  fetch < BACKGROUND_FETCHES + SPRITE_FETCHES + PREFETCHES {
+            Some((fetch - BACKGROUND_FETCHES - SPRITE_FETCHES) / 4)
+        } else {
+            None
+        }
+    }
+
+    fn sprite_fetch(&self) -> bool {
+        self.in_frame && self.fetches >= BACKGROUND_FETCHES &&
+        self.fetches < BACKGROUND_FETCHES + SPRITE_FETCHES
+    }
+
+    /// Where the nametable read at `addr` falls within the frame, as a scanline and fetch
+    /// index. The read that completes a scanline detection only reaches `ppu_bus_access`
+    /// after its data has been returned, so it is recognized here ahead of time.
+    fn name_table_fetch(&self, addr: u16) -> Option<(usize, usize)> {
+        if addr == self.last_read_addr && self.matching_reads == 1 {
+            match (self.in_frame, self.scanline) {
+                (false, _) => Some((0, 0)),
+                (true, scanline) if scanline < 239 => Some((scanline + 1, 0)),
+                _ => None,
+            }
+        } else if self.in_frame {
+            Some((self.scanline, self.fetches))
+        } else {
+            None
+        }
+    }
+
+    fn in_split(&self, column: usize) -> bool {
+        if self.split_control & SPLIT_ENABLE == 0 || self.exram_mode > 1 {
+            return false;
+        }
+
+        let threshold = (self.split_control & 0x1f) as usize;
+        if self.split_control & SPLIT_RIGHT != 0 {
+            column >= threshold
+        } else {
+            column < threshold
+        }
+    }
+
+    /// Substitutes split screen and extended attribute data for background fetches.
+    fn background_name_table_read(&mut self, addr: u16) -> Option<u8> {
+        let (scanline, fetch) = match self.name_table_fetch(addr) {
+            Some(position) => position,
+            None => return None,
+        };
+        let column = match Mmc5::background_column(fetch) {
+            Some(column) => column,
+            None => return None,
+        };
+
+        if fetch % 4 == 0 {
+            // The first two tiles fetched at the end of a line belong to the next one.
+            let line = if fetch >= BACKGROUND_FETCHES {
+                scanline + 1
+            } else {
+                scanline
+            };
+            self.split_tile = self.in_split(column);
+            self.split_y = (self.split_scroll as usize + line) % 240;
+
+            if self.split_tile {
+                return Some(self.exram[self.split_y / 8 * 32 + column % 32]);
+            }
+
+            if self.exram_mode == 1 {
+                self.ext_attribute = self.exram[addr as usize & 0x03ff];
+            }
+        } else if self.split_tile {
+            let attribute = self.exram[0x3c0 + self.split_y / 32 * 8 + column % 32 / 4];
+            let shift = (self.split_y / 16 & 0x01) << 2 | (column / 2 & 0x01) << 1;
+            return Some(((attribute >> shift) & 0x03) * 0x55);
+        } else if self.exram_mode == 1 {
+            return Some((self.ext_attribute >> 6) * 0x55);
+        }
+
+        None
+    }
+
+    fn detect_scanline(&mut self, addr: u16) {
+        if addr >= 0x2000 && addr < 0x3000 && addr == self.last_read_addr {
+            self.matching_reads += 1;
+        } else {
+            self.matching_reads = 0;
+        }
+        self.last_read_addr = addr;
+        self.fetches += 1;
+
+        // The PPU reads the same nametable byte three times in a row only at the start of
+        // a line: twice in the dummy fetches that end the previous one and once more for
+        // the first tile.
+        if self.matching_reads != 2 {
+            return;
+        }
+
+        self.fetches = 1;
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+        } else {
+            self.scanline += 1;
+            if self.scanline == 240 {
+                self.in_frame = false;
+            } else if self.scanline == self.irq_compare as usize {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn write_audio(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000 => self.pulse1.write_control(value),
+            0x5002 => self.pulse1.write_timer_low(value),
+            0x5003 => self.pulse1.write_timer_high(value),
+            0x5004 => self.pulse2.write_control(value),
+            0x5006 => self.pulse2.write_timer_low(value),
+            0x5007 => self.pulse2.write_timer_high(value),
+            // Read mode, where the DAC latches CPU reads from $8000-$BFFF, and its IRQ
+            // aren't emulated.
+            0x5011 => {
+                if value != 0 {
+                    self.pcm = value;
+                }
+            }
+            0x5015 => {
+                self.pulse1.length_counter.set_enabled(value & AUDIO_STATUS_PULSE1 != 0);
+                self.pulse2.length_counter.set_enabled(value & AUDIO_STATUS_PULSE2 != 0);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0xffff => {
+                match self.prg_offset(addr) {
+                    Ok(offset) => self.prg_rom[offset],
+                    Err(offset) => self.prg_ram[offset],
+                }
+            }
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0xdfff => {
+                if let Err(offset) = self.prg_offset(addr) {
+                    if self.prg_ram_writable() {
+                        self.prg_ram[offset] = value;
+                    }
+                }
+            }
+            0xe000...0xffff => {}
+            _ => panic!("MMC5 unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        let background = self.in_frame && !self.sprite_fetch();
+
+        if background && self.split_tile {
+            let offset = self.split_bank as usize * 0x1000 + (addr as usize & 0x0ff8) +
+                         (self.split_y & 0x07);
+            self.chr.read(offset)
+        } else if background && self.exram_mode == 1 {
+            let bank = (self.ext_attribute & 0x3f) as usize | (self.chr_upper as usize) << 6;
+            self.chr.read(bank * 0x1000 + (addr as usize & 0x0fff))
+        } else {
+            self.chr.read(self.chr_offset(addr, self.sprite_fetch()))
+        }
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr, false);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.name_table_mapping {
+            0x00 => Mirroring::SingleScreenLower,
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            0x55 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::FourScreen,
+        }
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn ppu_bus_access(&mut self, addr: u16) {
+        self.detect_scanline(addr);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn expansion_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5015 => {
+                let mut status = 0;
+                if self.pulse1.length_counter.active() {
+                    status |= AUDIO_STATUS_PULSE1;
+                }
+                if self.pulse2.length_counter.active() {
+                    status |= AUDIO_STATUS_PULSE2;
+                }
+                status
+            }
+            0x5204 => {
+                let mut status = 0;
+                if self.irq_pending {
+                    status |= IRQ_PENDING;
+                }
+                if self.in_frame {
+                    status |= IRQ_IN_FRAME;
+                }
+                self.irq_pending = false;
+                status
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5c00...0x5fff if self.exram_mode >= 2 => self.exram[addr as usize - 0x5c00],
+            _ => 0,
+        }
+    }
+
+    fn expansion_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000...0x5015 => self.write_audio(addr, value),
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 => self.prg_ram_protect[0] = value & 0x03,
+            0x5103 => self.prg_ram_protect[1] = value & 0x03,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.name_table_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113...0x5117 => self.prg_banks[addr as usize - 0x5113] = value,
+            0x5120...0x5127 => {
+                self.chr_banks_a[addr as usize - 0x5120] = value as u16 | self.chr_upper << 8;
+                self.last_chr_write_b = false;
+            }
+            0x5128...0x512b => {
+                self.chr_banks_b[addr as usize - 0x5128] = value as u16 | self.chr_upper << 8;
+                self.last_chr_write_b = true;
+            }
+            0x5130 => self.chr_upper = (value & 0x03) as u16,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & IRQ_ENABLE != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5c00...0x5fff => {
+                // In the nametable modes the PPU owns ExRAM while rendering and writes
+                // outside of it land as zeroes.
+                match self.exram_mode {
+                    0 | 1 => {
+                        self.exram[addr as usize - 0x5c00] = if self.in_frame { value } else { 0 }
+                    }
+                    2 => self.exram[addr as usize - 0x5c00] = value,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_register_write(&mut self, addr: u16, value: u8) {
+        match addr & 0x2007 {
+            0x2000 => self.sprites_8x16 = value & CTRL_SPRITE_SIZE_FLAG != 0,
+            0x2001 => {
+                if value & MASK_RENDERING_FLAGS == 0 {
+                    self.in_frame = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn name_table_read(&mut self, addr: u16, ciram: &[u8]) -> u8 {
+        if let Some(value) = self.background_name_table_read(addr) {
+            return value;
+        }
+
+        let offset = addr as usize & 0x03ff;
+        let table = (addr as usize >> 10) & 0x03;
+        match (self.name_table_mapping >> (table * 2)) & 0x03 {
+            0 => ciram[offset],
+            1 => ciram[0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if offset < 0x3c0 => self.fill_tile,
+            _ => self.fill_attribute * 0x55,
+        }
+    }
+
+    fn name_table_write(&mut self, addr: u16, value: u8, ciram: &mut [u8]) {
+        let offset = addr as usize & 0x03ff;
+        let table = (addr as usize >> 10) & 0x03;
+        match (self.name_table_mapping >> (table * 2)) & 0x03 {
+            0 => ciram[offset] = value,
+            1 => ciram[0x400 + offset] = value,
+            2 if self.exram_mode <= 1 => self.exram[offset] = value,
+            _ => {}
+        }
+    }
+
+    fn step_audio(&mut self) -> f32 {
+        self.audio_cycle += 1;
+
+        if self.audio_cycle % 2 == 0 {
+            self.pulse1.step_timer();
+            self.pulse2.step_timer();
+        }
+
+        if self.audio_cycle == AUDIO_FRAME_CYCLES {
+            self.audio_cycle = 0;
+            self.pulse1.clock_quarter_frame();
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_quarter_frame();
+            self.pulse2.clock_half_frame();
+        }
+
+        let pulse = self.pulse1.output() + self.pulse2.output();
+        pulse as f32 * PULSE_OUTPUT_SCALE + self.pcm as f32 * PCM_OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    fn new_mmc5() -> Mmc5 {
+        Mmc5::new(test_rom(5))
+    }
+
+    // Plays the nametable reads the PPU makes at the end of a line and the start of the
+    // next one, then the rest of the line's fetches.
+    fn fetch_scanline(mmc5: &mut Mmc5) {
+        let ciram = [0; 4096];
+        for _ in 0..3 {
+            mmc5.name_table_read(0x2000, &ciram);
+            mmc5.ppu_bus_access(0x2000);
+        }
+        for _ in 1..BACKGROUND_FETCHES + SPRITE_FETCHES + PREFETCHES {
+            mmc5.ppu_bus_access(0x1000);
+        }
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mmc5 = new_mmc5();
+        assert_eq!(mmc5.read(0xe000), 7);
+
+        mmc5.expansion_write(0x5100, 0x01);
+        mmc5.expansion_write(0x5115, 0x84);
+        assert_eq!(mmc5.read(0x8000), 2);
+        assert_eq!(mmc5.read(0xc000), 7);
+
+        // Without the RAM protect handshake writes are dropped.
+        mmc5.expansion_write(0x5113, 0x01);
+        mmc5.write(0x6000, 0xaa);
+        assert_eq!(mmc5.read(0x6000), 0);
+        mmc5.expansion_write(0x5102, 0x02);
+        mmc5.expansion_write(0x5103, 0x01);
+        mmc5.write(0x6000, 0xaa);
+        assert_eq!(mmc5.read(0x6000), 0xaa);
+
+        mmc5.expansion_write(0x5115, 0x01);
+        assert_eq!(mmc5.read(0xa000), 0xaa);
+    }
+
+    #[test]
+    fn test_pulse_without_sweep() {
+        // A period the APU's sweep unit would mute, at constant full volume and 50% duty.
+        let mut mmc5 = new_mmc5();
+        mmc5.expansion_write(0x5015, AUDIO_STATUS_PULSE1);
+        mmc5.expansion_write(0x5000, 0xbf);
+        mmc5.expansion_write(0x5002, 0x00);
+        mmc5.expansion_write(0x5003, 0x0d);
+
+        let mut peak = 0f32;
+        for _ in 0..30000 {
+            peak = peak.max(mmc5.step_audio());
+        }
+        assert_eq!(peak, 15.0 * PULSE_OUTPUT_SCALE);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut mmc5 = new_mmc5();
+        mmc5.expansion_write(0x5205, 200);
+        mmc5.expansion_write(0x5206, 100);
+        assert_eq!(mmc5.expansion_read(0x5205), (20000 & 0xff) as u8);
+        assert_eq!(mmc5.expansion_read(0x5206), (20000 >> 8) as u8);
+    }
+
+    #[test]
+    fn test_name_table_mapping() {
+        let mut mmc5 = new_mmc5();
+        let mut ciram = [0; 4096];
+        mmc5.expansion_write(0x5104, 0x02);
+        mmc5.expansion_write(0x5c05, 0x42);
+        mmc5.expansion_write(0x5104, 0x00);
+        mmc5.expansion_write(0x5105, 0xe4);
+        mmc5.expansion_write(0x5106, 0x33);
+        mmc5.expansion_write(0x5107, 0x02);
+
+        mmc5.name_table_write(0x2405, 0x11, &mut ciram);
+        assert_eq!(ciram[0x405], 0x11);
+        assert_eq!(mmc5.name_table_read(0x2805, &ciram), 0x42);
+        assert_eq!(mmc5.name_table_read(0x2c05, &ciram), 0x33);
+        assert_eq!(mmc5.name_table_read(0x2fc0, &ciram), 0xaa);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc5 = new_mmc5();
+        mmc5.expansion_write(0x5203, 2);
+        mmc5.expansion_write(0x5204, IRQ_ENABLE);
+
+        fetch_scanline(&mut mmc5);
+        assert_eq!(mmc5.expansion_read(0x5204), IRQ_IN_FRAME);
+        fetch_scanline(&mut mmc5);
+        assert!(!mmc5.irq());
+        fetch_scanline(&mut mmc5);
+        assert!(mmc5.irq());
+
+        assert_eq!(mmc5.expansion_read(0x5204), IRQ_PENDING | IRQ_IN_FRAME);
+        assert!(!mmc5.irq());
+    }
+
+    #[test]
+    fn test_sprite_chr_banks() {
+        let mut mmc5 = new_mmc5();
+        mmc5.ppu_register_write(0x2000, CTRL_SPRITE_SIZE_FLAG);
+        mmc5.expansion_write(0x5101, 0x00);
+        mmc5.expansion_write(0x5127, 0x05);
+        mmc5.expansion_write(0x512b, 0x09);
+
+        fetch_scanline(&mut mmc5);
+        let ciram = [0; 4096];
+        for _ in 0..3 {
+            mmc5.name_table_read(0x2000, &ciram);
+            mmc5.ppu_bus_access(0x2000);
+        }
+        assert_eq!(mmc5.chr_read(0x0000), 9);
+
+        for _ in 1..BACKGROUND_FETCHES {
+            mmc5.ppu_bus_access(0x1000);
+        }
+        assert_eq!(mmc5.chr_read(0x0000), 5);
+    }
+
+    #[test]
+    fn test_chr_set_outside_rendering() {
+        let mut mmc5 = new_mmc5();
+        mmc5.ppu_register_write(0x2000, CTRL_SPRITE_SIZE_FLAG);
+        mmc5.expansion_write(0x5101, 0x00);
+        mmc5.expansion_write(0x512b, 0x09);
+        mmc5.expansion_write(0x5127, 0x05);
+        assert_eq!(mmc5.chr_read(0x0000), 5);
+
+        mmc5.expansion_write(0x512b, 0x09);
+        assert_eq!(mmc5.chr_read(0x0000), 9);
+    }
+}
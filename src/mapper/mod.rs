@@ -1,6 +1,141 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod color_dreams;
+pub mod gxrom;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco163;
+pub mod nrom;
+pub mod opll;
 pub mod unrom;
+pub mod vrc4;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
+
+use rom::{Mirroring, Rom};
 
 pub trait Mapper {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, value: u8);
+    fn chr_read(&self, addr: u16) -> u8;
+    fn chr_write(&mut self, addr: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    /// Called with the address of every access the PPU makes to its bus, whether a
+    /// rendering fetch or a $2007 read or write. Reads are reported after the data has
+    /// been fetched, so bank switches triggered here apply from the next access.
+    fn ppu_bus_access(&mut self, _addr: u16) {}
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Called after the CPU has run for `cycles` cycles, for boards with timers clocked
+    /// by M2.
+    fn cpu_cycles_elapsed(&mut self, _cycles: u16) {}
+
+    /// CPU reads from the expansion area at $4020-$5FFF, where some boards put registers.
+    fn expansion_read(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn expansion_write(&mut self, _addr: u16, _value: u8) {}
+
+    /// Called for CPU writes to the PPU registers, which some boards snoop to follow the
+    /// PPU's configuration.
+    fn ppu_register_write(&mut self, _addr: u16, _value: u8) {}
+
+    /// Reads from the nametable space at $2000-$2FFF. The board decides what answers, so
+    /// it is handed the console's 2 KiB of CIRAM (plus the extra 2 KiB four-screen boards
+    /// carry) to map as it likes.
+    fn name_table_read(&mut self, addr: u16, ciram: &[u8]) -> u8 {
+        ciram[map_name_table_addr(addr, self.mirroring())]
+    }
+
+    fn name_table_write(&mut self, addr: u16, value: u8, ciram: &mut [u8]) {
+        ciram[map_name_table_addr(addr, self.mirroring())] = value;
+    }
+
+    /// The board's PRG RAM, as seen from $6000 with its first bank selected. Used to keep
+    /// battery-backed saves and to load trainers.
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Advances the board's expansion audio by one CPU cycle and returns its output, on
+    /// the same scale as the APU's mixer.
+    fn step_audio(&mut self) -> f32 {
+        0.0
+    }
+}
+
+pub fn map_name_table_addr(addr: u16, mirroring: Mirroring) -> usize {
+    let addr = (addr as usize - 0x2000) % 0x1000;
+    let table = addr / 0x400;
+
+    let table = match mirroring {
+        Mirroring::Horizontal => table / 2,
+        Mirroring::Vertical => table % 2,
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+        Mirroring::FourScreen => table,
+    };
+
+    table * 0x400 + addr % 0x400
+}
+
+/// A cartridge for mapper tests: eight 16K PRG banks and thirty-two 8K CHR banks, each
+/// filled with its own bank number.
+#[cfg(test)]
+pub fn test_rom(mapper: u16) -> Rom {
+    Rom {
+        prg_rom: (0..8).map(|bank| vec![bank as u8; 0x4000]).collect(),
+        chr_rom: (0..32).map(|bank| vec![bank as u8; 0x2000]).collect(),
+        mapper: mapper,
+        mirroring: Mirroring::Vertical,
+        ..Rom::default()
+    }
+}
+
+/// Pattern table memory for a cartridge: the CHR ROM from the image, or CHR RAM for
+/// boards that ship without any, 8 KiB unless a NES 2.0 header says otherwise. Addresses
+/// are flat offsets so mappers can bank switch by computing `bank * bank_size + offset`.
+pub struct Chr {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    pub fn new(rom: &Rom) -> Chr {
+        if rom.chr_rom.is_empty() {
+            let size = match rom.chr_ram_size + rom.chr_nvram_size {
+                0 => 8192,
+                size => size,
+            };
+            Chr {
+                data: vec![0; size],
+                writable: true,
+            }
+        } else {
+            Chr {
+                data: rom.chr_rom.concat(),
+                writable: false,
+            }
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        self.data[addr % self.data.len()]
+    }
+
+    pub fn write(&mut self, addr: usize, value: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[addr % len] = value;
+        }
+    }
 }
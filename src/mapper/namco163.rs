@@ -0,0 +1,385 @@
+use apu::PULSE_STEP_LEVEL;
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+// Bank numbers from here up select a page of CIRAM instead of CHR ROM.
+const CIRAM_BANKS: u8 = 0xe0;
+const SOUND_DISABLE: u8 = 0x40;
+const RAM_AUTO_INCREMENT: u8 = 0x80;
+const PRG_RAM_WRITE_ENABLE: u8 = 0x40;
+const IRQ_ENABLE: u8 = 0x80;
+const IRQ_COUNTER_MAX: u16 = 0x7fff;
+
+// The sound hardware updates one channel every 15 CPU cycles and only ever outputs the
+// channel it last updated, so more channels means a lower rate for each.
+const CHANNEL_UPDATE_CYCLES: u8 = 15;
+const CHANNEL_REGISTERS: usize = 0x40;
+// Channel samples are 4-bit and multiplied by a 4-bit volume, so at full volume one
+// sample step counts for one pulse step.
+const OUTPUT_SCALE: f32 = PULSE_STEP_LEVEL / 15.0;
+
+/// The wavetable synth. Its registers live in the top of the 128 bytes of internal RAM,
+/// eight per channel with channel 7 at the end, and the rest of the RAM holds 4-bit
+/// samples that any channel can play.
+struct Namco163Audio {
+    ram: [u8; 128],
+    cycles: u8,
+    channel: usize,
+    output: i16,
+}
+
+impl Namco163Audio {
+    fn new() -> Namco163Audio {
+        Namco163Audio {
+            ram: [0; 128],
+            cycles: 0,
+            channel: 0,
+            output: 0,
+        }
+    }
+
+    fn active_channels(&self) -> usize {
+        ((self.ram[0x7f] >> 4) & 0x07) as usize + 1
+    }
+
+    fn sample(&self, index: usize) -> u8 {
+        let byte = self.ram[(index & 0xff) >> 1];
+        if index % 2 == 0 {
+            byte & 0x0f
+        } else {
+            byte >> 4
+        }
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = CHANNEL_REGISTERS + channel * 8;
+        let registers = &self.ram[base..base + 8];
+        let frequency = registers[0] as u32 | (registers[2] as u32) << 8 |
+                        ((registers[4] & 0x03) as u32) << 16;
+        let phase = registers[1] as u32 | (registers[3] as u32) << 8 |
+                    (registers[5] as u32) << 16;
+        let length = (256 - (registers[4] & 0xfc) as u32) << 16;
+        let wave_address = registers[6] as usize;
+        let volume = (registers[7] & 0x0f) as i16;
+
+        let phase = (phase + frequency) % length;
+        let sample = self.sample((phase >> 16) as usize + wave_address);
+        self.output = (sample as i16 - 8) * volume;
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+    }
+
+    fn step(&mut self) -> i16 {
+        self.cycles += 1;
+        if self.cycles == CHANNEL_UPDATE_CYCLES {
+            self.cycles = 0;
+
+            // Channels are updated from 7 downwards, wrapping back once the last active
+            // one has had its turn.
+            let lowest = 8 - self.active_channels();
+            self.channel = if self.channel <= lowest {
+                7
+            } else {
+                self.channel - 1
+            };
+            let channel = self.channel;
+            self.update_channel(channel);
+        }
+
+        self.output
+    }
+}
+
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    name_table_banks: [u8; 4],
+    ram_address: u8,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq: bool,
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn new(rom: Rom) -> Namco163 {
+        Namco163 {
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; 8192],
+            chr: Chr::new(&rom),
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            name_table_banks: [CIRAM_BANKS; 4],
+            ram_address: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000...0xdfff => {
+                (self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] & 0x3f) as usize
+            }
+            _ => self.prg_rom.len() / PRG_BANK_SIZE - 1,
+        };
+
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    // Pattern table banks pointed at CIRAM would need the PPU's nametable memory, which
+    // no released game relies on, so those banks read CHR ROM like any other.
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn name_table_bank(&self, addr: u16) -> u8 {
+        self.name_table_banks[(addr as usize >> 10) & 0x03]
+    }
+
+    fn advance_ram_address(&mut self) {
+        if self.ram_address & RAM_AUTO_INCREMENT != 0 {
+            self.ram_address = RAM_AUTO_INCREMENT | self.ram_address.wrapping_add(1) & 0x7f;
+        }
+    }
+
+    /// The $F800 register doubles as the PRG RAM write protect: the upper nibble must
+    /// be 4 and each of the low bits protects a 2K quarter of the RAM.
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        let quarter = (addr as usize - 0x6000) / 0x800;
+        self.ram_address & 0xf0 == PRG_RAM_WRITE_ENABLE && self.ram_address & (1 << quarter) == 0
+    }
+}
+
+impl Mapper for Namco163 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000...0xffff => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {
+                if self.prg_ram_writable(addr) {
+                    self.prg_ram[(addr - 0x6000) as usize] = value;
+                }
+            }
+            0x8000...0xbfff => self.chr_banks[(addr as usize - 0x8000) / 0x800] = value,
+            0xc000...0xdfff => self.name_table_banks[(addr as usize - 0xc000) / 0x800] = value,
+            0xe000...0xf7ff => self.prg_banks[(addr as usize - 0xe000) / 0x800] = value,
+            0xf800...0xffff => self.ram_address = value,
+            _ => panic!("Namco 163 unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        let banks = self.name_table_banks;
+        match (banks[0] & 0x01, banks[1] & 0x01, banks[2] & 0x01, banks[3] & 0x01) {
+            (0, 0, 0, 0) => Mirroring::SingleScreenLower,
+            (0, 1, 0, 1) => Mirroring::Vertical,
+            (0, 0, 1, 1) => Mirroring::Horizontal,
+            (1, 1, 1, 1) => Mirroring::SingleScreenUpper,
+            _ => Mirroring::FourScreen,
+        }
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_cycles_elapsed(&mut self, cycles: u16) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        // The counter counts up and stops once it reaches the top.
+        let remaining = IRQ_COUNTER_MAX - self.irq_counter;
+        self.irq_counter += cycles.min(remaining);
+        if self.irq_counter == IRQ_COUNTER_MAX {
+            self.irq = true;
+        }
+    }
+
+    fn expansion_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800...0x4fff => {
+                let value = self.audio.ram[(self.ram_address & 0x7f) as usize];
+                self.advance_ram_address();
+                value
+            }
+            0x5000...0x57ff => self.irq_counter as u8,
+            0x5800...0x5fff => {
+                (self.irq_counter >> 8) as u8 | if self.irq_enabled { IRQ_ENABLE } else { 0 }
+            }
+            _ => 0,
+        }
+    }
+
+    fn expansion_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4800...0x4fff => {
+                self.audio.ram[(self.ram_address & 0x7f) as usize] = value;
+                self.advance_ram_address();
+            }
+            0x5000...0x57ff => {
+                self.irq_counter = self.irq_counter & 0x7f00 | value as u16;
+                self.irq = false;
+            }
+            0x5800...0x5fff => {
+                self.irq_counter = self.irq_counter & 0x00ff | ((value & 0x7f) as u16) << 8;
+                self.irq_enabled = value & IRQ_ENABLE != 0;
+                self.irq = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn name_table_read(&mut self, addr: u16, ciram: &[u8]) -> u8 {
+        let bank = self.name_table_bank(addr);
+        let offset = addr as usize & 0x03ff;
+        if bank >= CIRAM_BANKS {
+            ciram[(bank as usize & 0x01) * 0x400 + offset]
+        } else {
+            self.chr.read(bank as usize * CHR_BANK_SIZE + offset)
+        }
+    }
+
+    fn name_table_write(&mut self, addr: u16, value: u8, ciram: &mut [u8]) {
+        let bank = self.name_table_bank(addr);
+        let offset = addr as usize & 0x03ff;
+        if bank >= CIRAM_BANKS {
+            ciram[(bank as usize & 0x01) * 0x400 + offset] = value;
+        } else {
+            self.chr.write(bank as usize * CHR_BANK_SIZE + offset, value);
+        }
+    }
+
+    fn step_audio(&mut self) -> f32 {
+        let output = self.audio.step();
+        if self.prg_banks[0] & SOUND_DISABLE != 0 {
+            return 0.0;
+        }
+
+        output as f32 * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    fn new_namco163() -> Namco163 {
+        Namco163::new(test_rom(19))
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut namco163 = new_namco163();
+        namco163.write(0xe000, 0x02);
+        namco163.write(0xf000, 0x05);
+        namco163.write(0xb800, 0x18);
+        assert_eq!(namco163.read(0x8000), 1);
+        assert_eq!(namco163.read(0xc000), 2);
+        assert_eq!(namco163.read(0xe000), 7);
+        assert_eq!(namco163.chr_read(0x1c00), 3);
+    }
+
+    #[test]
+    fn test_name_tables() {
+        let mut namco163 = new_namco163();
+        let mut ciram = [0u8; 0x800];
+        ciram[0x405] = 0x42;
+
+        namco163.write(0xc000, 0xe1);
+        namco163.write(0xc800, 0x10);
+        assert_eq!(namco163.name_table_read(0x2005, &ciram), 0x42);
+        assert_eq!(namco163.name_table_read(0x2405, &ciram), 2);
+
+        namco163.name_table_write(0x2005, 0x99, &mut ciram);
+        assert_eq!(ciram[0x405], 0x99);
+    }
+
+    #[test]
+    fn test_ram_port_and_irq() {
+        let mut namco163 = new_namco163();
+        namco163.write(0xf800, RAM_AUTO_INCREMENT | 0x10);
+        namco163.expansion_write(0x4800, 0x12);
+        namco163.expansion_write(0x4800, 0x34);
+        namco163.write(0xf800, RAM_AUTO_INCREMENT | 0x10);
+        assert_eq!(namco163.expansion_read(0x4800), 0x12);
+        assert_eq!(namco163.expansion_read(0x4800), 0x34);
+
+        namco163.expansion_write(0x5000, 0xfd);
+        namco163.expansion_write(0x5800, IRQ_ENABLE | 0x7f);
+        namco163.cpu_cycles_elapsed(1);
+        assert!(!namco163.irq());
+        namco163.cpu_cycles_elapsed(5);
+        assert!(namco163.irq());
+        assert_eq!(namco163.expansion_read(0x5000), 0xff);
+
+        namco163.expansion_write(0x5000, 0x00);
+        assert!(!namco163.irq());
+    }
+
+    #[test]
+    fn test_ram_address_wraps() {
+        let mut namco163 = new_namco163();
+        namco163.write(0xf800, RAM_AUTO_INCREMENT);
+        for value in 0..129 {
+            namco163.expansion_write(0x4800, value as u8);
+        }
+
+        // The 129th write wrapped around to $00 and overwrote the first.
+        namco163.write(0xf800, RAM_AUTO_INCREMENT | 0x7f);
+        assert_eq!(namco163.expansion_read(0x4800), 127);
+        assert_eq!(namco163.expansion_read(0x4800), 128);
+        assert_eq!(namco163.expansion_read(0x4800), 1);
+    }
+
+    #[test]
+    fn test_channel_multiplexing() {
+        let mut audio = Namco163Audio::new();
+        // A wave of all 15s at address 0, played by channels 7 and 6 at different
+        // volumes with two channels enabled.
+        for byte in audio.ram[0..8].iter_mut() {
+            *byte = 0xff;
+        }
+        audio.ram[0x7c] = 0xe0;
+        audio.ram[0x7f] = 0x10 | 0x0f;
+        audio.ram[0x74] = 0xe0;
+        audio.ram[0x77] = 0x01;
+
+        let outputs: Vec<i16> = (0..60).map(|_| audio.step()).collect();
+        assert_eq!(outputs[14], 7 * 15);
+        assert_eq!(outputs[29], 7);
+        assert_eq!(outputs[44], 7 * 15);
+        assert_eq!(outputs[59], 7);
+    }
+}
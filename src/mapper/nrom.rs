@@ -0,0 +1,60 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Nrom {
+        Nrom {
+            prg_rom: rom.prg_rom.concat(),
+            // Only Family Basic carts have PRG RAM, but nothing else touches $6000 so it
+            // is always mapped.
+            prg_ram: vec![0; 8192],
+            chr: Chr::new(&rom),
+            mirroring: rom.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff => self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()],
+            // 16 KiB boards mirror their only bank into $C000-$FFFF.
+            0x8000...0xffff => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000...0x7fff => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            0x8000...0xffff => {}
+            _ => panic!("NROM unimplemented write {:x} = {}", addr, value),
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        self.chr.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
@@ -0,0 +1,417 @@
+use std::f32::consts::PI;
+
+// The synth runs off the cartridge's own 3.58MHz crystal, twice the CPU clock, and
+// produces a sample every 72 ticks of it.
+const CPU_CYCLES_PER_SAMPLE: u32 = 36;
+const SAMPLE_RATE: f32 = 49716.0;
+const CHANNELS: usize = 6;
+
+const MODULATOR: usize = 0;
+const CARRIER: usize = 1;
+const PATCH_AM: u8 = 0x80;
+const PATCH_VIBRATO: u8 = 0x40;
+const PATCH_SUSTAINED: u8 = 0x20;
+const PATCH_KEY_SCALE_RATE: u8 = 0x10;
+const CHANNEL_SUSTAIN: u8 = 0x20;
+const CHANNEL_KEY_ON: u8 = 0x10;
+
+const MAX_ATTENUATION: f32 = 48.0;
+// Seconds for the envelope to sweep 96dB at rate 4, the slowest that moves at all. Each
+// further step of four halves the time.
+const ATTACK_TIME: f32 = 2.826;
+const DECAY_TIME: f32 = 39.28;
+const TREMOLO_FREQUENCY: f32 = 3.7;
+const TREMOLO_DEPTH: f32 = 4.8;
+const VIBRATO_FREQUENCY: f32 = 6.4;
+// About seven cents either side of the note.
+const VIBRATO_DEPTH: f32 = 0.004;
+// A full scale modulator swings the carrier's phase by 8 pi.
+const MODULATION_DEPTH: f32 = 4.0;
+
+/// The VRC7's built-in instruments 1-15. Instrument 0 is the user-defined patch.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12],
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4],
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02],
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6],
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06],
+];
+
+static MULTIPLIERS: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0,
+                                 12.0, 12.0, 15.0, 15.0];
+
+// Attenuation in dB at 6dB per octave, indexed by the top four bits of the F-number.
+static KEY_SCALE_LEVELS: [f32; 16] = [0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25,
+                                      36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+/// How far the envelope moves in one sample, in dB, for a 6-bit effective rate.
+fn envelope_step(rate: u8, time: f32) -> f32 {
+    if rate < 4 {
+        return 0.0;
+    }
+
+    let rate = rate.min(63);
+    let fine = 1.0 + (rate % 4) as f32 / 4.0;
+    96.0 / (time * SAMPLE_RATE) * 2f32.powi((rate / 4) as i32 - 1) * fine
+}
+
+#[derive(Clone, Copy)]
+struct Operator {
+    phase: f32,
+    attenuation: f32,
+    state: EnvelopeState,
+    outputs: [f32; 2],
+}
+
+impl Operator {
+    fn new() -> Operator {
+        Operator {
+            phase: 0.0,
+            attenuation: MAX_ATTENUATION,
+            state: EnvelopeState::Off,
+            outputs: [0.0; 2],
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    fn step_envelope(&mut self, patch: &[u8; 8], operator: usize, key_scale: u8, sustain: bool) {
+        let flags = patch[operator];
+        let key_scale = if flags & PATCH_KEY_SCALE_RATE != 0 {
+            key_scale
+        } else {
+            key_scale >> 2
+        };
+        let attack = patch[4 + operator] >> 4;
+        let decay = patch[4 + operator] & 0x0f;
+        let sustain_level = (patch[6 + operator] >> 4) as f32 * 3.0;
+        let release = patch[6 + operator] & 0x0f;
+        let rate = |r: u8| if r == 0 { 0 } else { r * 4 + key_scale };
+
+        match self.state {
+            EnvelopeState::Attack => {
+                if rate(attack) >= 60 {
+                    self.attenuation = 0.0;
+                } else {
+                    self.attenuation -= envelope_step(rate(attack), ATTACK_TIME);
+                }
+
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.attenuation += envelope_step(rate(decay), DECAY_TIME);
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                // Percussive patches keep fading at the release rate while the key is held.
+                if flags & PATCH_SUSTAINED == 0 {
+                    self.attenuation += envelope_step(rate(release), DECAY_TIME);
+                }
+            }
+            EnvelopeState::Release => {
+                let release = if sustain {
+                    5
+                } else if flags & PATCH_SUSTAINED != 0 {
+                    release
+                } else {
+                    7
+                };
+                self.attenuation += envelope_step(rate(release), DECAY_TIME);
+            }
+            EnvelopeState::Off => {}
+        }
+
+        if self.attenuation >= MAX_ATTENUATION {
+            self.attenuation = MAX_ATTENUATION;
+            if self.state != EnvelopeState::Attack {
+                self.state = EnvelopeState::Off;
+            }
+        }
+    }
+
+    /// Produces the operator's next output in -1.0 to 1.0, with its phase pushed along by
+    /// `modulation` cycles.
+    fn output(&mut self, increment: f32, modulation: f32, attenuation: f32, rectified: bool)
+              -> f32 {
+        self.phase = (self.phase + increment) % 1.0;
+
+        let mut wave = (2.0 * PI * (self.phase + modulation)).sin();
+        if rectified && wave < 0.0 {
+            wave = 0.0;
+        }
+
+        let output = if self.state == EnvelopeState::Off {
+            0.0
+        } else {
+            wave * 10f32.powf(-(self.attenuation + attenuation) / 20.0)
+        };
+
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    f_number: u16,
+    block: u8,
+    flags: u8,
+    instrument: u8,
+    volume: u8,
+    operators: [Operator; 2],
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            f_number: 0,
+            block: 0,
+            flags: 0,
+            instrument: 0,
+            volume: 0,
+            operators: [Operator::new(); 2],
+        }
+    }
+
+    fn write_flags(&mut self, value: u8) {
+        let was_on = self.flags & CHANNEL_KEY_ON != 0;
+        let key_on = value & CHANNEL_KEY_ON != 0;
+
+        self.flags = value;
+        self.block = (value >> 1) & 0x07;
+        self.f_number = self.f_number & 0xff | ((value & 0x01) as u16) << 8;
+
+        if key_on && !was_on {
+            self.operators[MODULATOR].key_on();
+            self.operators[CARRIER].key_on();
+        } else if !key_on && was_on {
+            self.operators[MODULATOR].key_off();
+            self.operators[CARRIER].key_off();
+        }
+    }
+
+    fn key_scale_level(&self, ksl: u8) -> f32 {
+        let level = KEY_SCALE_LEVELS[(self.f_number >> 5) as usize] -
+                    6.0 * (7 - self.block) as f32;
+
+        match ksl {
+            _ if level <= 0.0 => 0.0,
+            0 => 0.0,
+            1 => level / 4.0,
+            2 => level / 2.0,
+            _ => level,
+        }
+    }
+
+    fn step(&mut self, patch: &[u8; 8], tremolo: f32, vibrato: f32) -> f32 {
+        let key_scale = self.block << 1 | (self.f_number >> 8) as u8;
+        let sustain = self.flags & CHANNEL_SUSTAIN != 0;
+        let base_increment = (self.f_number as f32) * 2f32.powi(self.block as i32) /
+                             524288.0;
+
+        let mut modulation = 0.0;
+        let mut output = 0.0;
+        for operator in 0..2 {
+            let flags = patch[operator];
+            let mut increment = base_increment * MULTIPLIERS[(flags & 0x0f) as usize];
+            if flags & PATCH_VIBRATO != 0 {
+                increment *= vibrato;
+            }
+
+            let mut attenuation = self.key_scale_level(patch[2 + operator] >> 6);
+            if flags & PATCH_AM != 0 {
+                attenuation += tremolo;
+            }
+
+            // The modulator has its own total level and feedback, the carrier takes the
+            // channel volume and the modulator's output.
+            let rectified = patch[3] & (0x08 << operator) != 0;
+            if operator == MODULATOR {
+                attenuation += (patch[2] & 0x3f) as f32 * 0.75;
+
+                let feedback = patch[3] & 0x07;
+                if feedback > 0 {
+                    let outputs = self.operators[MODULATOR].outputs;
+                    modulation = (outputs[0] + outputs[1]) / 2.0 *
+                                 2f32.powi(feedback as i32 - 1) / 32.0;
+                }
+            } else {
+                attenuation += self.volume as f32 * 3.0;
+            }
+
+            let op = &mut self.operators[operator];
+            op.step_envelope(patch, operator, key_scale, sustain);
+            output = op.output(increment, modulation, attenuation, rectified);
+            modulation = output * MODULATION_DEPTH;
+        }
+
+        output
+    }
+}
+
+/// A software model of the YM2413-derived FM synth in the VRC7: six two-operator channels
+/// with fifteen fixed instruments and one programmable one.
+pub struct Opll {
+    custom_patch: [u8; 8],
+    address: u8,
+    channels: [Channel; CHANNELS],
+    cycles: u32,
+    tremolo_phase: f32,
+    vibrato_phase: f32,
+    output: f32,
+}
+
+impl Opll {
+    pub fn new() -> Opll {
+        Opll {
+            custom_patch: [0; 8],
+            address: 0,
+            channels: [Channel::new(); CHANNELS],
+            cycles: 0,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        let channel = (self.address & 0x0f) as usize;
+
+        match self.address {
+            0x00...0x07 => self.custom_patch[self.address as usize] = value,
+            0x10...0x15 => {
+                let channel = &mut self.channels[channel];
+                channel.f_number = channel.f_number & 0x100 | value as u16;
+            }
+            0x20...0x25 => self.channels[channel].write_flags(value),
+            0x30...0x35 => {
+                self.channels[channel].instrument = value >> 4;
+                self.channels[channel].volume = value & 0x0f;
+            }
+            _ => {}
+        }
+    }
+
+    /// Advances the synth by one CPU cycle and returns the sum of its channels.
+    pub fn step(&mut self) -> f32 {
+        self.cycles += 1;
+        if self.cycles < CPU_CYCLES_PER_SAMPLE {
+            return self.output;
+        }
+        self.cycles = 0;
+
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_FREQUENCY / SAMPLE_RATE) % 1.0;
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_FREQUENCY / SAMPLE_RATE) % 1.0;
+        let tremolo = TREMOLO_DEPTH * (1.0 - (2.0 * PI * self.tremolo_phase).cos()) / 2.0;
+        let vibrato = 1.0 + VIBRATO_DEPTH * (2.0 * PI * self.vibrato_phase).sin();
+
+        self.output = 0.0;
+        for channel in self.channels.iter_mut() {
+            let patch = match channel.instrument {
+                0 => self.custom_patch,
+                instrument => PATCHES[instrument as usize - 1],
+            };
+            self.output += channel.step(&patch, tremolo, vibrato);
+        }
+
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(opll: &mut Opll, address: u8, value: u8) {
+        opll.write_address(address);
+        opll.write_data(value);
+    }
+
+    fn run(opll: &mut Opll, samples: u32) -> f32 {
+        let mut peak = 0f32;
+        for _ in 0..samples * CPU_CYCLES_PER_SAMPLE {
+            peak = peak.max(opll.step().abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn test_key_on_and_release() {
+        let mut opll = Opll::new();
+        assert_eq!(run(&mut opll, 100), 0.0);
+
+        // A440 on the flute patch at full volume.
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x30, 0x40);
+        write(&mut opll, 0x20, CHANNEL_KEY_ON | 0x08 | 0x01);
+        assert!(run(&mut opll, 2000) > 0.5);
+
+        write(&mut opll, 0x20, 0x08 | 0x01);
+        run(&mut opll, 50000);
+        assert_eq!(opll.channels[0].operators[CARRIER].state, EnvelopeState::Off);
+        assert_eq!(run(&mut opll, 100), 0.0);
+    }
+
+    #[test]
+    fn test_volume() {
+        let mut loud = Opll::new();
+        let mut quiet = Opll::new();
+        for (opll, volume) in vec![(&mut loud, 0x40), (&mut quiet, 0x44)] {
+            write(opll, 0x10, 0x20);
+            write(opll, 0x30, volume);
+            write(opll, 0x20, CHANNEL_KEY_ON | 0x09);
+        }
+
+        // Four steps of 3dB is a factor of four in amplitude.
+        let ratio = run(&mut loud, 4000) / run(&mut quiet, 4000);
+        assert!(ratio > 3.5 && ratio < 4.5);
+    }
+
+    #[test]
+    fn test_custom_patch() {
+        let mut opll = Opll::new();
+        for (address, &value) in PATCHES[0].iter().enumerate() {
+            write(&mut opll, address as u8, value);
+        }
+        assert_eq!(opll.custom_patch, PATCHES[0]);
+    }
+}
@@ -1,15 +1,19 @@
-use rom::Rom;
-use mapper::Mapper;
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
 
 pub struct Unrom {
+    chr: Chr,
     rom: Rom,
+    bus_conflicts: bool,
     active_bank: usize,
 }
 
 impl Unrom {
-    pub fn new(rom: Rom) -> Unrom {
+    pub fn new(rom: Rom, bus_conflicts: bool) -> Unrom {
         Unrom {
+            chr: Chr::new(&rom),
             rom: rom,
+            bus_conflicts: bus_conflicts,
             active_bank: 0,
         }
     }
@@ -20,6 +24,7 @@ impl Mapper for Unrom {
         let prg_rom = &self.rom.prg_rom;
 
         match addr {
+            0x6000...0x7fff => 0,
             0x8000...0xbfff => prg_rom[self.active_bank][(addr - 0x8000) as usize],
             0xc000...0xffff => prg_rom[prg_rom.len() - 1][(addr - 0xc000) as usize],
             _ => panic!("Illegal memory address for mapper: {}", addr),
@@ -28,8 +33,54 @@ impl Mapper for Unrom {
 
     fn write(&mut self, addr: u16, value: u8) {
         match addr {
-            0x8000...0xffff => self.active_bank = value as usize % self.rom.prg_rom.len(),
+            0x6000...0x7fff => {}
+            0x8000...0xffff => {
+                let value = if self.bus_conflicts {
+                    value & self.read(addr)
+                } else {
+                    value
+                };
+                self.active_bank = value as usize % self.rom.prg_rom.len();
+            }
             _ => panic!("UNROM unimplemented write {:x} = {}", addr, value),
         }
     }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        self.chr.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.rom.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    #[test]
+    fn test_banking() {
+        let mut unrom = Unrom::new(test_rom(2), false);
+        unrom.write(0x8000, 0x03);
+        assert_eq!(unrom.read(0x8000), 3);
+        assert_eq!(unrom.read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // Each bank is filled with its own number, so the ROM drives that number onto the
+        // bus during the write.
+        let mut unrom = Unrom::new(test_rom(2), true);
+        unrom.write(0xc000, 0x05);
+        assert_eq!(unrom.read(0x8000), 5);
+
+        unrom.write(0x8000, 0x06);
+        assert_eq!(unrom.read(0x8000), 4);
+    }
 }
@@ -0,0 +1,280 @@
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+use mapper::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+const PRG_SWAP_MODE: u8 = 0x02;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Revision {
+    Vrc2,
+    Vrc4,
+}
+
+/// How a board connects the chip's two register select lines to the CPU address bus.
+/// Each line is given as a mask of the CPU address bits that drive it; boards with an
+/// unknown submapper decode both candidate wirings at once.
+struct Wiring {
+    revision: Revision,
+    a0: u16,
+    a1: u16,
+    // VRC2a ignores the low bit of its CHR bank numbers.
+    chr_shift: u8,
+}
+
+fn wiring(mapper: u16, submapper: u8) -> Wiring {
+    let (revision, a0, a1, chr_shift) = match (mapper, submapper) {
+        (21, 1) => (Revision::Vrc4, 0x02, 0x04, 0),
+        (21, 2) => (Revision::Vrc4, 0x40, 0x80, 0),
+        (21, _) => (Revision::Vrc4, 0x42, 0x84, 0),
+        (22, _) => (Revision::Vrc2, 0x02, 0x01, 1),
+        (23, 1) => (Revision::Vrc4, 0x01, 0x02, 0),
+        (23, 2) => (Revision::Vrc4, 0x04, 0x08, 0),
+        (23, 3) => (Revision::Vrc2, 0x01, 0x02, 0),
+        (23, _) => (Revision::Vrc4, 0x05, 0x0a, 0),
+        (25, 1) => (Revision::Vrc4, 0x02, 0x01, 0),
+        (25, 2) => (Revision::Vrc4, 0x08, 0x04, 0),
+        (25, 3) => (Revision::Vrc2, 0x02, 0x01, 0),
+        (25, _) => (Revision::Vrc4, 0x0a, 0x05, 0),
+        _ => panic!("Not a VRC2/VRC4 mapper: {}", mapper),
+    };
+
+    Wiring {
+        revision: revision,
+        a0: a0,
+        a1: a1,
+        chr_shift: chr_shift,
+    }
+}
+
+pub struct Vrc4 {
+    wiring: Wiring,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    prg_banks: [u8; 2],
+    prg_mode: u8,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(rom: Rom) -> Vrc4 {
+        Vrc4 {
+            wiring: wiring(rom.mapper, rom.submapper),
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; 8192],
+            chr: Chr::new(&rom),
+            prg_banks: [0, 1],
+            prg_mode: 0,
+            chr_banks: [0; 8],
+            mirroring: rom.mirroring,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    /// Folds a CPU address down to one of the chip's registers, $x000-$x003.
+    fn register(&self, addr: u16) -> u16 {
+        let mut register = addr & 0xf000;
+        if addr & self.wiring.a0 != 0 {
+            register |= 0x01;
+        }
+        if addr & self.wiring.a1 != 0 {
+            register |= 0x02;
+        }
+        register
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let last_bank = self.prg_rom.len() / PRG_BANK_SIZE - 1;
+        let swapped = self.prg_mode & PRG_SWAP_MODE != 0;
+
+        let bank = match (addr, swapped) {
+            (0x8000...0x9fff, false) | (0xc000...0xdfff, true) => self.prg_banks[0] as usize,
+            (0x8000...0x9fff, true) | (0xc000...0xdfff, false) => last_bank - 1,
+            (0xa000...0xbfff, _) => self.prg_banks[1] as usize,
+            _ => last_bank,
+        };
+
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] >> self.wiring.chr_shift;
+        bank as usize * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_chr_bank(&mut self, register: u16, value: u8) {
+        let index = ((register - 0xb000) >> 12) as usize * 2 + (register as usize & 0x02) / 2;
+        let bank = self.chr_banks[index];
+
+        self.chr_banks[index] = if register & 0x01 == 0 {
+            bank & 0x1f0 | (value & 0x0f) as u16
+        } else {
+            bank & 0x00f | ((value & 0x1f) as u16) << 4
+        };
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000...0xffff => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if let 0x6000...0x7fff = addr {
+            self.prg_ram[(addr - 0x6000) as usize] = value;
+            return;
+        }
+
+        let vrc4 = self.wiring.revision == Revision::Vrc4;
+        match self.register(addr) {
+            0x8000...0x8003 => self.prg_banks[0] = value & 0x1f,
+            0x9000...0x9001 if vrc4 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0x9000...0x9003 if !vrc4 => {
+                self.mirroring = if value & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0x9002...0x9003 => self.prg_mode = value,
+            0xa000...0xa003 => self.prg_banks[1] = value & 0x1f,
+            register @ 0xb000...0xefff => self.write_chr_bank(register, value),
+            0xf000 if vrc4 => self.irq.write_latch_low(value),
+            0xf001 if vrc4 => self.irq.write_latch_high(value),
+            0xf002 if vrc4 => self.irq.write_control(value),
+            0xf003 if vrc4 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycles_elapsed(&mut self, cycles: u16) {
+        self.irq.step(cycles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+    use mapper::vrc_irq::{IRQ_CYCLE_MODE, IRQ_ENABLE};
+    use rom::Rom;
+
+    fn new_vrc4(mapper: u16, submapper: u8) -> Vrc4 {
+        Vrc4::new(Rom {
+            submapper: submapper,
+            ..test_rom(mapper)
+        })
+    }
+
+    #[test]
+    fn test_address_lines() {
+        // VRC4a and VRC4c put the same registers at different addresses.
+        let mut vrc4a = new_vrc4(21, 1);
+        vrc4a.write(0xb004, 0x08);
+        assert_eq!(vrc4a.chr_read(0x0400), 1);
+        vrc4a.write(0xb080, 0x08);
+        assert_eq!(vrc4a.chr_read(0x0400), 1);
+
+        let mut vrc4c = new_vrc4(21, 2);
+        vrc4c.write(0xb080, 0x08);
+        assert_eq!(vrc4c.chr_read(0x0400), 1);
+
+        let mut unknown = new_vrc4(21, 0);
+        unknown.write(0xb004, 0x08);
+        unknown.write(0xb0c0, 0x01);
+        assert_eq!(unknown.chr_read(0x0400), 3);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut vrc4 = new_vrc4(25, 1);
+        vrc4.write(0x8000, 0x04);
+        vrc4.write(0xa000, 0x07);
+        assert_eq!(vrc4.read(0x8000), 2);
+        assert_eq!(vrc4.read(0xa000), 3);
+        assert_eq!(vrc4.read(0xc000), 7);
+
+        vrc4.write(0x9001, PRG_SWAP_MODE);
+        assert_eq!(vrc4.read(0x8000), 7);
+        assert_eq!(vrc4.read(0xc000), 2);
+        assert_eq!(vrc4.read(0xe000), 7);
+    }
+
+    #[test]
+    fn test_vrc2a_chr_banks() {
+        let mut vrc2 = new_vrc4(22, 0);
+        vrc2.write(0xc000, 0x06);
+        assert_eq!(vrc2.chr_read(0x0800), 0);
+        vrc2.write(0xc000, 0x00);
+        vrc2.write(0xc002, 0x01);
+        assert_eq!(vrc2.chr_read(0x0800), 1);
+    }
+
+    #[test]
+    fn test_cycle_irq() {
+        let mut vrc4 = new_vrc4(23, 1);
+        vrc4.write(0xf000, 0x0c);
+        vrc4.write(0xf001, 0x0f);
+        vrc4.write(0xf002, IRQ_ENABLE | IRQ_CYCLE_MODE);
+
+        vrc4.cpu_cycles_elapsed(3);
+        assert!(!vrc4.irq());
+        vrc4.cpu_cycles_elapsed(1);
+        assert!(vrc4.irq());
+
+        vrc4.write(0xf003, 0);
+        assert!(!vrc4.irq());
+        vrc4.cpu_cycles_elapsed(100);
+        assert!(!vrc4.irq());
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut vrc4 = new_vrc4(23, 1);
+        vrc4.write(0xf001, 0x0f);
+        vrc4.write(0xf000, 0x0e);
+        vrc4.write(0xf002, IRQ_ENABLE);
+
+        // Two scanlines take 227 or 228 CPU cycles.
+        vrc4.cpu_cycles_elapsed(226);
+        assert!(!vrc4.irq());
+        vrc4.cpu_cycles_elapsed(2);
+        assert!(vrc4.irq());
+    }
+}
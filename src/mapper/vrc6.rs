@@ -0,0 +1,368 @@
+use apu::PULSE_STEP_LEVEL;
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+use mapper::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+const PRG_RAM_ENABLE: u8 = 0x80;
+const CHANNEL_ENABLE: u8 = 0x80;
+const PULSE_IGNORE_DUTY: u8 = 0x80;
+const FREQUENCY_HALT: u8 = 0x01;
+const FREQUENCY_SHIFT_4: u8 = 0x02;
+const FREQUENCY_SHIFT_8: u8 = 0x04;
+// The pulses have the same 4-bit volume as the APU's, and the sawtooth's steps are as big.
+const OUTPUT_SCALE: f32 = PULSE_STEP_LEVEL;
+
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn new() -> Vrc6Pulse {
+        Vrc6Pulse {
+            volume: 0,
+            duty: 0,
+            ignore_duty: false,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.ignore_duty = value & PULSE_IGNORE_DUTY != 0;
+        self.duty = (value >> 4) & 0x07;
+        self.volume = value & 0x0f;
+    }
+
+    fn write_period_low(&mut self, value: u8) {
+        self.period = self.period & 0x0f00 | value as u16;
+    }
+
+    fn write_period_high(&mut self, value: u8) {
+        self.period = self.period & 0x00ff | ((value & 0x0f) as u16) << 8;
+        self.enabled = value & CHANNEL_ENABLE != 0;
+
+        if !self.enabled {
+            self.step = 0;
+        }
+    }
+
+    fn step_timer(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+struct Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Sawtooth {
+        Sawtooth {
+            rate: 0,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write_rate(&mut self, value: u8) {
+        self.rate = value & 0x3f;
+    }
+
+    fn write_period_low(&mut self, value: u8) {
+        self.period = self.period & 0x0f00 | value as u16;
+    }
+
+    fn write_period_high(&mut self, value: u8) {
+        self.period = self.period & 0x00ff | ((value & 0x0f) as u16) << 8;
+        self.enabled = value & CHANNEL_ENABLE != 0;
+
+        if !self.enabled {
+            self.step = 0;
+            self.accumulator = 0;
+        }
+    }
+
+    // The rate is added on every other clock and the accumulator is cleared on the
+    // fourteenth, so a rate above 42 overflows.
+    fn step_timer(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step % 2 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+pub struct Vrc6 {
+    // Mapper 26 boards swap the A0 and A1 lines.
+    swap_address_lines: bool,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    control: u8,
+    mirroring: Mirroring,
+    irq: VrcIrq,
+    frequency_control: u8,
+    pulse1: Vrc6Pulse,
+    pulse2: Vrc6Pulse,
+    sawtooth: Sawtooth,
+}
+
+impl Vrc6 {
+    pub fn new(rom: Rom) -> Vrc6 {
+        Vrc6 {
+            swap_address_lines: rom.mapper == 26,
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; 8192],
+            chr: Chr::new(&rom),
+            prg_banks: [0, 0],
+            chr_banks: [0; 8],
+            control: 0,
+            mirroring: rom.mirroring,
+            irq: VrcIrq::new(),
+            frequency_control: 0,
+            pulse1: Vrc6Pulse::new(),
+            pulse2: Vrc6Pulse::new(),
+            sawtooth: Sawtooth::new(),
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        if self.swap_address_lines {
+            addr & 0xf000 | (addr & 0x01) << 1 | (addr & 0x02) >> 1
+        } else {
+            addr & 0xf003
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let (bank, size) = match addr {
+            0x8000...0xbfff => (self.prg_banks[0] as usize * 2, PRG_BANK_SIZE * 2),
+            0xc000...0xdfff => (self.prg_banks[1] as usize, PRG_BANK_SIZE),
+            _ => (self.prg_rom.len() / PRG_BANK_SIZE - 1, PRG_BANK_SIZE),
+        };
+
+        (bank * PRG_BANK_SIZE + (addr as usize & (size - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    // Only the register layout every released game uses is supported: 1K CHR banks with
+    // the nametables in CIRAM.
+    fn write_control(&mut self, value: u8) {
+        self.control = value;
+        self.mirroring = match (value >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        };
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff if self.control & PRG_RAM_ENABLE != 0 => {
+                self.prg_ram[(addr - 0x6000) as usize]
+            }
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if let 0x6000...0x7fff = addr {
+            if self.control & PRG_RAM_ENABLE != 0 {
+                self.prg_ram[(addr - 0x6000) as usize] = value;
+            }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000...0x8003 => self.prg_banks[0] = value & 0x0f,
+            0x9000 => self.pulse1.write_control(value),
+            0x9001 => self.pulse1.write_period_low(value),
+            0x9002 => self.pulse1.write_period_high(value),
+            0x9003 => self.frequency_control = value,
+            0xa000 => self.pulse2.write_control(value),
+            0xa001 => self.pulse2.write_period_low(value),
+            0xa002 => self.pulse2.write_period_high(value),
+            0xb000 => self.sawtooth.write_rate(value),
+            0xb001 => self.sawtooth.write_period_low(value),
+            0xb002 => self.sawtooth.write_period_high(value),
+            0xb003 => self.write_control(value),
+            0xc000...0xc003 => self.prg_banks[1] = value & 0x1f,
+            register @ 0xd000...0xd003 => self.chr_banks[(register & 0x03) as usize] = value,
+            register @ 0xe000...0xe003 => {
+                self.chr_banks[4 + (register & 0x03) as usize] = value
+            }
+            0xf000 => self.irq.write_latch(value),
+            0xf001 => self.irq.write_control(value),
+            0xf002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycles_elapsed(&mut self, cycles: u16) {
+        self.irq.step(cycles);
+    }
+
+    fn step_audio(&mut self) -> f32 {
+        if self.frequency_control & FREQUENCY_HALT == 0 {
+            let shift = if self.frequency_control & FREQUENCY_SHIFT_8 != 0 {
+                8
+            } else if self.frequency_control & FREQUENCY_SHIFT_4 != 0 {
+                4
+            } else {
+                0
+            };
+
+            self.pulse1.step_timer(shift);
+            self.pulse2.step_timer(shift);
+            self.sawtooth.step_timer(shift);
+        }
+
+        let output = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        output as f32 * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+
+    fn new_vrc6(mapper: u16) -> Vrc6 {
+        Vrc6::new(test_rom(mapper))
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut vrc6 = new_vrc6(24);
+        vrc6.write(0x8000, 0x03);
+        vrc6.write(0xc000, 0x09);
+        vrc6.write(0xe002, 0x10);
+        assert_eq!(vrc6.read(0x8000), 3);
+        assert_eq!(vrc6.read(0xc000), 4);
+        assert_eq!(vrc6.read(0xe000), 7);
+        assert_eq!(vrc6.chr_read(0x1800), 2);
+
+        // On mapper 26 the same write lands in the register next door.
+        let mut vrc6 = new_vrc6(26);
+        vrc6.write(0xe002, 0x10);
+        assert_eq!(vrc6.chr_read(0x1400), 2);
+    }
+
+    #[test]
+    fn test_sawtooth() {
+        let mut sawtooth = Sawtooth::new();
+        sawtooth.write_rate(42);
+        sawtooth.write_period_high(CHANNEL_ENABLE);
+
+        let mut outputs = Vec::new();
+        for _ in 0..14 {
+            sawtooth.step_timer(0);
+            outputs.push(sawtooth.output());
+        }
+
+        assert_eq!(outputs, vec![0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0]);
+    }
+
+    #[test]
+    fn test_pulse_duty() {
+        let mut pulse = Vrc6Pulse::new();
+        pulse.write_control(0x3f);
+        pulse.write_period_high(CHANNEL_ENABLE);
+
+        let mut high = 0;
+        for _ in 0..16 {
+            pulse.step_timer(0);
+            if pulse.output() == 15 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 4);
+
+        pulse.write_control(0x8f);
+        assert_eq!(pulse.output(), 15);
+    }
+}
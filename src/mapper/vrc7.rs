@@ -0,0 +1,226 @@
+use apu::PULSE_STEP_LEVEL;
+use rom::{Mirroring, Rom};
+use mapper::{Chr, Mapper};
+use mapper::opll::Opll;
+use mapper::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+const CONTROL_AUDIO_RESET: u8 = 0x40;
+const CONTROL_PRG_RAM_ENABLE: u8 = 0x80;
+// Each FM channel swings between -1.0 and 1.0. The VRC7 is quieter than the other chips:
+// a six-note chord at full volume peaks about as loud as the APU's two pulses together.
+const OUTPUT_SCALE: f32 = 5.0 * PULSE_STEP_LEVEL;
+
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8,
+    mirroring: Mirroring,
+    irq: VrcIrq,
+    opll: Opll,
+}
+
+impl Vrc7 {
+    pub fn new(rom: Rom) -> Vrc7 {
+        Vrc7 {
+            prg_rom: rom.prg_rom.concat(),
+            prg_ram: vec![0; 8192],
+            chr: Chr::new(&rom),
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            mirroring: rom.mirroring,
+            irq: VrcIrq::new(),
+            opll: Opll::new(),
+        }
+    }
+
+    /// VRC7a boards select the second register of each pair with A4 and VRC7b boards
+    /// with A3, so either line is accepted.
+    fn register(addr: u16) -> u16 {
+        match addr & 0xf030 {
+            0x9030 => 0x9030,
+            _ if addr & 0x18 != 0 => addr & 0xf000 | 0x10,
+            _ => addr & 0xf000,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0x8000...0xdfff => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize,
+            _ => self.prg_rom.len() / PRG_BANK_SIZE - 1,
+        };
+
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.control = value;
+        self.mirroring = match value & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        };
+
+        if value & CONTROL_AUDIO_RESET != 0 {
+            self.opll = Opll::new();
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000...0x7fff if self.control & CONTROL_PRG_RAM_ENABLE != 0 => {
+                self.prg_ram[(addr - 0x6000) as usize]
+            }
+            0x6000...0x7fff => 0,
+            0x8000...0xffff => self.prg_rom[self.prg_rom_offset(addr)],
+            _ => panic!("Illegal memory address for mapper: {}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if let 0x6000...0x7fff = addr {
+            if self.control & CONTROL_PRG_RAM_ENABLE != 0 {
+                self.prg_ram[(addr - 0x6000) as usize] = value;
+            }
+            return;
+        }
+
+        match Vrc7::register(addr) {
+            0x8000 => self.prg_banks[0] = value & 0x3f,
+            0x8010 => self.prg_banks[1] = value & 0x3f,
+            0x9000 => self.prg_banks[2] = value & 0x3f,
+            0x9010 => self.opll.write_address(value),
+            0x9030 => self.opll.write_data(value),
+            register @ 0xa000...0xd010 => {
+                let index = ((register - 0xa000) >> 12) as usize * 2 +
+                            (register as usize & 0x10) / 0x10;
+                self.chr_banks[index] = value;
+            }
+            0xe000 => self.write_control(value),
+            0xe010 => self.irq.write_latch(value),
+            0xf000 => self.irq.write_control(value),
+            0xf010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycles_elapsed(&mut self, cycles: u16) {
+        self.irq.step(cycles);
+    }
+
+    fn step_audio(&mut self) -> f32 {
+        // Holding the reset bit keeps the synth silent.
+        if self.control & CONTROL_AUDIO_RESET != 0 {
+            return 0.0;
+        }
+
+        self.opll.step() * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mapper::{test_rom, Mapper};
+    use rom::Mirroring;
+
+    fn new_vrc7() -> Vrc7 {
+        Vrc7::new(test_rom(85))
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut vrc7 = new_vrc7();
+        vrc7.write(0x8000, 0x02);
+        vrc7.write(0x8010, 0x04);
+        vrc7.write(0x9000, 0x06);
+        assert_eq!(vrc7.read(0x8000), 1);
+        assert_eq!(vrc7.read(0xa000), 2);
+        assert_eq!(vrc7.read(0xc000), 3);
+        assert_eq!(vrc7.read(0xe000), 7);
+
+        // A3 and A4 both select the odd CHR register.
+        vrc7.write(0xd008, 0x10);
+        assert_eq!(vrc7.chr_read(0x1c00), 2);
+        vrc7.write(0xd010, 0x18);
+        assert_eq!(vrc7.chr_read(0x1c00), 3);
+
+        vrc7.write(0xe000, 0x01);
+        assert_eq!(vrc7.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_audio_ports() {
+        let mut vrc7 = new_vrc7();
+        vrc7.write(0x9010, 0x10);
+        vrc7.write(0x9030, 0x20);
+        vrc7.write(0x9010, 0x30);
+        vrc7.write(0x9030, 0x40);
+        vrc7.write(0x9010, 0x20);
+        vrc7.write(0x9030, 0x19);
+
+        let mut peak = 0f32;
+        for _ in 0..100000 {
+            peak = peak.max(vrc7.step_audio().abs());
+        }
+        assert!(peak > 0.0);
+
+        vrc7.write(0xe000, CONTROL_AUDIO_RESET);
+        assert_eq!(vrc7.step_audio(), 0.0);
+    }
+
+    #[test]
+    fn test_chord_level() {
+        // Six notes on the reed organ patch with the volume at its loudest.
+        let mut vrc7 = new_vrc7();
+        for channel in 0..6 {
+            vrc7.write(0x9010, 0x10 + channel);
+            vrc7.write(0x9030, 0x80 + channel * 0x10);
+            vrc7.write(0x9010, 0x30 + channel);
+            vrc7.write(0x9030, 0x80);
+            vrc7.write(0x9010, 0x20 + channel);
+            vrc7.write(0x9030, 0x19);
+        }
+
+        let mut peak = 0f32;
+        for _ in 0..100000 {
+            peak = peak.max(vrc7.step_audio().abs());
+        }
+        assert!(peak > 0.1);
+        assert!(peak <= 30.0 * PULSE_STEP_LEVEL);
+    }
+}
@@ -0,0 +1,92 @@
+pub const IRQ_ENABLE_AFTER_ACK: u8 = 0x01;
+pub const IRQ_ENABLE: u8 = 0x02;
+pub const IRQ_CYCLE_MODE: u8 = 0x04;
+// In scanline mode the prescaler divides the CPU clock by 113.667, counting down by
+// three from 341 every cycle.
+const PRESCALER_PERIOD: i16 = 341;
+
+/// The IRQ counter Konami shares between the VRC4, VRC6 and VRC7. It counts up from a
+/// latched value to $FF, either every CPU cycle or once per scanline's worth of them.
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    control: u8,
+    prescaler: i16,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            control: 0,
+            prescaler: PRESCALER_PERIOD,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    /// The VRC4 takes the latch a nibble at a time.
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = self.latch & 0xf0 | value & 0x0f;
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = self.latch & 0x0f | value << 4;
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.control = value;
+        self.pending = false;
+
+        if value & IRQ_ENABLE != 0 {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+
+        if self.control & IRQ_ENABLE_AFTER_ACK != 0 {
+            self.control |= IRQ_ENABLE;
+        } else {
+            self.control &= !IRQ_ENABLE;
+        }
+    }
+
+    pub fn step(&mut self, cycles: u16) {
+        if self.control & IRQ_ENABLE == 0 {
+            return;
+        }
+
+        for _ in 0..cycles {
+            if self.control & IRQ_CYCLE_MODE != 0 {
+                self.clock_counter();
+            } else {
+                self.prescaler -= 3;
+                if self.prescaler <= 0 {
+                    self.prescaler += PRESCALER_PERIOD;
+                    self.clock_counter();
+                }
+            }
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use cpu::Cpu;
 use interconnect::MemoryMappingInterconnect;
 use joypad::ButtonState;
-use rom::Rom;
+use rom::{ConsoleType, Rom, RomError};
 
 pub struct Nes {
     pub interconnect: MemoryMappingInterconnect,
@@ -9,11 +9,17 @@ pub struct Nes {
 }
 
 impl Nes {
-    pub fn new(rom: Rom) -> Nes {
-        Nes {
-            interconnect: MemoryMappingInterconnect::new(rom),
-            cpu: Cpu::new(),
+    pub fn new(rom: Rom) -> Result<Nes, RomError> {
+        match rom.console_type {
+            // PlayChoice-10 games run on ordinary boards; only the hint screens are lost.
+            ConsoleType::Nes | ConsoleType::Playchoice10 => {}
+            console_type => return Err(RomError::UnsupportedConsoleType(console_type)),
         }
+
+        Ok(Nes {
+            interconnect: MemoryMappingInterconnect::new(rom)?,
+            cpu: Cpu::new(),
+        })
     }
 
     pub fn reset(&mut self) {
@@ -26,6 +32,32 @@ impl Nes {
         let mut frame_in_progress = true;
         while frame_in_progress {
             let cycles = self.cpu.step(&mut self.interconnect);
+            if self.clock(cycles) {
+                frame_in_progress = false;
+            }
+
+            if self.interconnect.irq() {
+                let cycles = self.cpu.irq(&mut self.interconnect);
+                if self.clock(cycles) {
+                    frame_in_progress = false;
+                }
+            }
+        }
+
+        &self.interconnect.ppu.screen
+    }
+
+    fn clock(&mut self, mut cycles: u16) -> bool {
+        let mut end_frame = false;
+
+        // DMC fetches made during these cycles halt the CPU, so the stall is run straight
+        // away instead of being charged to whatever the CPU does next.
+        while cycles > 0 {
+            self.interconnect.step_mapper(cycles);
+
+            for _ in 0..cycles {
+                self.interconnect.step_apu();
+            }
 
             for _ in 0..cycles * 3 {
                 let result = self.interconnect.ppu.step();
@@ -35,11 +67,71 @@ impl Nes {
                 }
 
                 if result.end_frame {
-                    frame_in_progress = false;
+                    end_frame = true;
                 }
             }
+
+            cycles = self.interconnect.take_stall_cycles();
         }
 
-        &self.interconnect.ppu.screen
+        end_frame
+    }
+
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.interconnect.apu.take_samples()
+    }
+
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.interconnect.battery_ram()
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        self.interconnect.load_battery_ram(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interconnect::Interconnect;
+    use rom::TRAINER_SIZE;
+
+    #[test]
+    fn test_run_from_memory() {
+        let mut image = b"NES\x1a\x01\x01\x00\x00".to_vec();
+        image.extend(vec![0; 8]);
+
+        // LDA #$42, STA $00, then spin, with every vector pointing at the start.
+        let mut prg_rom = vec![0; 16384];
+        prg_rom[0..7].copy_from_slice(&[0xa9, 0x42, 0x85, 0x00, 0x4c, 0x04, 0x80]);
+        prg_rom[0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        image.extend(prg_rom);
+        image.extend(vec![0; 8192]);
+
+        let mut nes = Nes::new(Rom::from_bytes(&image).unwrap()).unwrap();
+        nes.reset();
+        nes.run_frame(ButtonState::default());
+        assert_eq!(nes.interconnect.read_word(0x0000), 0x42);
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut image = b"NES\x1a\x01\x01\x04\x00".to_vec();
+        image.extend(vec![0; 8]);
+        image.extend((0..TRAINER_SIZE).map(|i| i as u8));
+        image.extend(vec![0; 16384 + 8192]);
+
+        let mut nes = Nes::new(Rom::from_bytes(&image).unwrap()).unwrap();
+        assert_eq!(nes.interconnect.read_word(0x7000), 0x00);
+        assert_eq!(nes.interconnect.read_word(0x7001), 0x01);
+        assert_eq!(nes.interconnect.read_word(0x71ff), 0xff);
+    }
+
+    #[test]
+    fn test_missing_prg_rom() {
+        match Nes::new(Rom::default()) {
+            Err(RomError::MissingPrgRom) => {}
+            _ => panic!("Expected MissingPrgRom"),
+        }
     }
 }
@@ -1,13 +1,34 @@
 mod vram;
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mapper::Mapper;
 use self::vram::Vram;
 
 pub const CTRL_INCR_FLAG: u8 = 0x02;
+const CTRL_SPRITE_FLAG: u8 = 0x08;
 const CTRL_BACKGROUND_FLAG: u8 = 0x10;
+const CTRL_SPRITE_SIZE_FLAG: u8 = 0x20;
 const CTRL_NMI_FLAG: u8 = 0x80;
+const MASK_GRAYSCALE: u8 = 0x01;
+const MASK_BACKGROUND_LEFT: u8 = 0x02;
+const MASK_SPRITES_LEFT: u8 = 0x04;
 const MASK_DISPLAY_BACKGROUND: u8 = 0x08;
 const MASK_DISPLAY_SPRITES: u8 = 0x10;
+const MASK_EMPHASIZE_RED: u8 = 0x20;
+const MASK_EMPHASIZE_GREEN: u8 = 0x40;
+const MASK_EMPHASIZE_BLUE: u8 = 0x80;
+// Emphasized output dims every channel other than the emphasized ones to roughly 80%.
+const EMPHASIS_ATTENUATION: u32 = 816;
+const STATUS_SPRITE_OVERFLOW_FLAG: u8 = 0x20;
+const STATUS_SPRITE_ZERO_HIT_FLAG: u8 = 0x40;
 const STATUS_VBLANK_FLAG: u8 = 0x80;
+const SPRITE_ATTR_PALETTE: u8 = 0x03;
+const SPRITE_ATTR_PRIORITY: u8 = 0x20;
+const SPRITE_ATTR_FLIP_HORIZONTAL: u8 = 0x40;
+const SPRITE_ATTR_FLIP_VERTICAL: u8 = 0x80;
+const MAX_SPRITES_PER_LINE: usize = 8;
 const PIXELS: usize = 256 * 240;
 
 static SYSTEM_PALETTE: [u32; 64] =
@@ -45,7 +66,17 @@ pub struct Ppu {
     write_flag: bool,
     vram: Vram,
     spr_ram: [u8; 256],
-    pub screen: [u32; PIXELS],
+    secondary_oam: [u8; 32],
+    evaluated_sprites: usize,
+    sprite_zero_evaluated: bool,
+    sprite_count: usize,
+    sprite_zero_on_line: bool,
+    sprite_low_bytes: [u8; MAX_SPRITES_PER_LINE],
+    sprite_high_bytes: [u8; MAX_SPRITES_PER_LINE],
+    sprite_attributes: [u8; MAX_SPRITES_PER_LINE],
+    sprite_positions: [u8; MAX_SPRITES_PER_LINE],
+    // Boxed so that moving a `Ppu`, or anything holding one, stays cheap.
+    pub screen: Box<[u32; PIXELS]>,
     name_table_byte: u8,
     attribute_table_byte: u8,
     low_bg_tile_byte: u8,
@@ -57,7 +88,7 @@ pub struct Ppu {
 }
 
 impl Ppu {
-    pub fn new() -> Ppu {
+    pub fn new(mapper: Rc<RefCell<Mapper>>) -> Ppu {
         Ppu {
             ctrl: 0,
             mask: 0,
@@ -67,9 +98,18 @@ impl Ppu {
             vram_addr: 0,
             tmp_vram_addr: 0,
             write_flag: false,
-            vram: Vram::new(),
+            vram: Vram::new(mapper),
             spr_ram: [0; 256],
-            screen: [0; PIXELS],
+            secondary_oam: [0xff; 32],
+            evaluated_sprites: 0,
+            sprite_zero_evaluated: false,
+            sprite_count: 0,
+            sprite_zero_on_line: false,
+            sprite_low_bytes: [0; MAX_SPRITES_PER_LINE],
+            sprite_high_bytes: [0; MAX_SPRITES_PER_LINE],
+            sprite_attributes: [0; MAX_SPRITES_PER_LINE],
+            sprite_positions: [0; MAX_SPRITES_PER_LINE],
+            screen: Box::new([0; PIXELS]),
             name_table_byte: 0,
             attribute_table_byte: 0,
             low_bg_tile_byte: 0,
@@ -162,6 +202,8 @@ impl Ppu {
                 0...239 => {
                     self.process_render_scanline();
                     self.process_fetch_scanline();
+                    self.process_sprite_evaluation();
+                    self.process_sprite_fetch();
 
                     if self.cycle == 257 {
                         self.vram_addr = (self.vram_addr & 0xFBE0) | (self.tmp_vram_addr & 0x041F);
@@ -170,6 +212,12 @@ impl Ppu {
                 -1 | 261 => {
                     self.process_fetch_scanline();
 
+                    if self.cycle == 1 {
+                        self.evaluated_sprites = 0;
+                        self.sprite_zero_evaluated = false;
+                    }
+                    self.process_sprite_fetch();
+
                     if self.cycle >= 280 && self.cycle <= 304 {
                         self.vram_addr = (self.vram_addr & 0x841F) | (self.tmp_vram_addr & 0x7BE0);
                     }
@@ -180,6 +228,8 @@ impl Ppu {
 
         if self.scanline == 261 && self.cycle == 1 {
             self.set_vblank(false);
+            self.set_sprite_zero_hit(false);
+            self.set_sprite_overflow(false);
         } else if self.scanline == 241 && self.cycle == 1 {
             self.set_vblank(true);
             if self.nmi_flag() {
@@ -200,18 +250,214 @@ impl Ppu {
             return;
         }
 
+        let x = (self.cycle - 1) as u8;
+        let bg_pixel = self.background_pixel(x);
+        let sprite = self.sprite_pixel(x);
+
+        // Left-column clipping has already made clipped pixels transparent, which
+        // is also what suppresses the hit there.
+        if let Some((0, _, _)) = sprite {
+            if self.sprite_zero_on_line && bg_pixel & 0x03 != 0 && x != 255 {
+                self.set_sprite_zero_hit(true);
+            }
+        }
+
+        let color_addr = match sprite {
+            Some((_, _, sprite_pixel)) if bg_pixel & 0x03 == 0 => 0x3f10 | sprite_pixel as u16,
+            Some((_, attributes, sprite_pixel)) if attributes & SPRITE_ATTR_PRIORITY == 0 => {
+                0x3f10 | sprite_pixel as u16
+            }
+            _ if bg_pixel & 0x03 == 0 => 0x3f00,
+            _ => 0x3f00 | bg_pixel as u16,
+        };
+
+        let palette_index = self.vram.read(color_addr);
+        self.screen[256 * self.scanline as usize + x as usize] = self.palette_color(palette_index);
+    }
+
+    fn palette_color(&self, palette_index: u8) -> u32 {
+        let palette_index = if self.mask & MASK_GRAYSCALE != 0 {
+            palette_index & 0x30
+        } else {
+            palette_index
+        };
+        let color = SYSTEM_PALETTE[(palette_index % 64) as usize];
+
+        let emphasis = self.mask &
+                       (MASK_EMPHASIZE_RED | MASK_EMPHASIZE_GREEN | MASK_EMPHASIZE_BLUE);
+        if emphasis == 0 {
+            return color;
+        }
+
+        let attenuate = |channel: u32, flag: u8| if emphasis & !flag != 0 {
+            channel * EMPHASIS_ATTENUATION / 1000
+        } else {
+            channel
+        };
+        let r = attenuate((color >> 16) & 0xff, MASK_EMPHASIZE_RED);
+        let g = attenuate((color >> 8) & 0xff, MASK_EMPHASIZE_GREEN);
+        let b = attenuate(color & 0xff, MASK_EMPHASIZE_BLUE);
+
+        r << 16 | g << 8 | b
+    }
+
+    fn background_pixel(&self, x: u8) -> u8 {
+        if !self.display_background() || (x < 8 && self.mask & MASK_BACKGROUND_LEFT == 0) {
+            return 0;
+        }
+
         let shift = 32 + (7 - self.scroll) * 4;
-        let bg_color_addr = (0x3f00 | self.tile_data >> shift) as u16;
-        let bg_palette_index = self.vram.read(bg_color_addr);
-        let color = SYSTEM_PALETTE[(bg_palette_index % 64) as usize];
-        self.screen[256 * self.scanline as usize + self.cycle as usize - 1] = color;
+        ((self.tile_data >> shift) & 0x0f) as u8
+    }
+
+    fn sprite_pixel(&self, x: u8) -> Option<(usize, u8, u8)> {
+        if !self.display_sprites() || (x < 8 && self.mask & MASK_SPRITES_LEFT == 0) {
+            return None;
+        }
+
+        for i in 0..self.sprite_count {
+            let offset = x.wrapping_sub(self.sprite_positions[i]);
+            if offset > 7 {
+                continue;
+            }
+
+            let shift = 7 - offset;
+            let low_bit = (self.sprite_low_bytes[i] >> shift) & 0x01;
+            let high_bit = (self.sprite_high_bytes[i] >> shift) & 0x01;
+            let pixel = high_bit << 1 | low_bit;
+            if pixel == 0 {
+                continue;
+            }
+
+            let attributes = self.sprite_attributes[i];
+            return Some((i, attributes, (attributes & SPRITE_ATTR_PALETTE) << 2 | pixel));
+        }
+
+        None
+    }
+
+    fn process_sprite_evaluation(&mut self) {
+        match self.cycle {
+            64 => self.secondary_oam = [0xff; 32],
+            256 => self.evaluate_sprites(),
+            _ => {}
+        }
+    }
+
+    fn evaluate_sprites(&mut self) {
+        let mut count = 0;
+        let mut n = 0;
+        let mut m = 0;
+
+        self.sprite_zero_evaluated = false;
+
+        while n < 64 {
+            if count < MAX_SPRITES_PER_LINE {
+                let y = self.spr_ram[n * 4];
+                if self.sprite_in_range(y) {
+                    self.secondary_oam[count * 4..count * 4 + 4]
+                        .copy_from_slice(&self.spr_ram[n * 4..n * 4 + 4]);
+                    self.sprite_zero_evaluated |= n == 0;
+                    count += 1;
+                }
+                n += 1;
+            } else {
+                // Once secondary OAM is full the hardware keeps incrementing the byte
+                // offset along with the sprite index, so it compares tile numbers,
+                // attributes and X positions as if they were Y coordinates.
+                let y = self.spr_ram[n * 4 + m];
+                if self.sprite_in_range(y) {
+                    self.set_sprite_overflow(true);
+                    break;
+                }
+                n += 1;
+                m = (m + 1) % 4;
+            }
+        }
+
+        self.evaluated_sprites = count;
+    }
+
+    fn sprite_in_range(&self, y: u8) -> bool {
+        let row = self.scanline - y as i16;
+        row >= 0 && row < self.sprite_height() as i16
+    }
+
+    fn process_sprite_fetch(&mut self) {
+        if self.cycle < 257 || self.cycle > 320 {
+            return;
+        }
+
+        self.spr_ram_addr = 0;
+
+        let slot = ((self.cycle - 257) / 8) as usize;
+        match self.cycle % 8 {
+            5 => self.fetch_low_sprite_tile_byte(slot),
+            7 => self.fetch_high_sprite_tile_byte(slot),
+            0 => {
+                if slot == MAX_SPRITES_PER_LINE - 1 {
+                    self.sprite_count = self.evaluated_sprites;
+                    self.sprite_zero_on_line = self.sprite_zero_evaluated;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn sprite_pattern_addr(&self, slot: usize) -> u16 {
+        let y = self.secondary_oam[slot * 4];
+        let tile = self.secondary_oam[slot * 4 + 1] as u16;
+        let attributes = self.secondary_oam[slot * 4 + 2];
+
+        let height = self.sprite_height();
+        let mut row = (self.scanline - y as i16) as u16 & (height - 1);
+        if attributes & SPRITE_ATTR_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+
+        if height == 16 {
+            // 8x16 sprites take their pattern table from bit 0 of the tile number and
+            // use an even/odd pair of tiles for the top and bottom halves.
+            let table = (tile & 0x01) * 0x1000;
+            let tile = (tile & 0xfe) + row / 8;
+            table + tile * 16 + row % 8
+        } else {
+            self.sprite_pattern_table() + tile * 16 + row
+        }
+    }
+
+    fn fetch_low_sprite_tile_byte(&mut self, slot: usize) {
+        let addr = self.sprite_pattern_addr(slot);
+        self.sprite_low_bytes[slot] = self.fetch_sprite_tile_byte(slot, addr);
+        self.sprite_attributes[slot] = self.secondary_oam[slot * 4 + 2];
+        self.sprite_positions[slot] = self.secondary_oam[slot * 4 + 3];
+    }
+
+    fn fetch_high_sprite_tile_byte(&mut self, slot: usize) {
+        let addr = self.sprite_pattern_addr(slot) + 8;
+        self.sprite_high_bytes[slot] = self.fetch_sprite_tile_byte(slot, addr);
+    }
+
+    fn fetch_sprite_tile_byte(&mut self, slot: usize, addr: u16) -> u8 {
+        // Empty slots still perform a (dummy) fetch but never produce pixels.
+        let value = self.vram.read(addr);
+
+        if slot >= self.evaluated_sprites {
+            0
+        } else if self.secondary_oam[slot * 4 + 2] & SPRITE_ATTR_FLIP_HORIZONTAL != 0 {
+            value.reverse_bits()
+        } else {
+            value
+        }
     }
 
     fn process_fetch_scanline(&mut self) {
         match self.cycle {
             1...256 | 321...336 => self.fetch_bg_data(),
             257 => self.increment_y(),
-            320 => {}
+            // Two unused nametable fetches end the line; MMC5 counts them to find the
+            // start of the next one.
+            337 | 339 => self.fetch_name_table_byte(),
             _ => {}
         }
     }
@@ -334,6 +580,22 @@ impl Ppu {
         }
     }
 
+    fn sprite_pattern_table(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_FLAG == 0 {
+            0
+        } else {
+            0x1000
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_SIZE_FLAG == 0 {
+            8
+        } else {
+            16
+        }
+    }
+
     fn nmi_flag(&self) -> bool {
         self.ctrl & CTRL_NMI_FLAG != 0
     }
@@ -353,15 +615,69 @@ impl Ppu {
             self.status & !STATUS_VBLANK_FLAG
         };
     }
+
+    fn set_sprite_zero_hit(&mut self, value: bool) {
+        self.status = if value {
+            self.status | STATUS_SPRITE_ZERO_HIT_FLAG
+        } else {
+            self.status & !STATUS_SPRITE_ZERO_HIT_FLAG
+        };
+    }
+
+    fn set_sprite_overflow(&mut self, value: bool) {
+        self.status = if value {
+            self.status | STATUS_SPRITE_OVERFLOW_FLAG
+        } else {
+            self.status & !STATUS_SPRITE_OVERFLOW_FLAG
+        };
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mapper::Mapper;
+    use rom::Mirroring;
+
+    struct TestMapper {
+        chr: [u8; 8192],
+        mirroring: Mirroring,
+    }
+
+    impl Mapper for TestMapper {
+        fn read(&self, _: u16) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _: u16, _: u8) {}
+
+        fn chr_read(&self, addr: u16) -> u8 {
+            self.chr[addr as usize]
+        }
+
+        fn chr_write(&mut self, addr: u16, value: u8) {
+            self.chr[addr as usize] = value;
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            self.mirroring
+        }
+    }
+
+    fn new_ppu_with_mirroring(mirroring: Mirroring) -> Ppu {
+        Ppu::new(Rc::new(RefCell::new(TestMapper {
+            chr: [0; 8192],
+            mirroring: mirroring,
+        })))
+    }
+
+    fn new_ppu() -> Ppu {
+        new_ppu_with_mirroring(Mirroring::Horizontal)
+    }
 
     #[test]
     fn test_write_ctrl() {
-        let mut ppu = Ppu::new();
+        let mut ppu = new_ppu();
 
         ppu.write_ctrl(0xff);
 
@@ -371,7 +687,7 @@ mod tests {
 
     #[test]
     fn test_write_scroll() {
-        let mut ppu = Ppu::new();
+        let mut ppu = new_ppu();
 
         ppu.write_scroll(0x7d);
 
@@ -387,7 +703,7 @@ mod tests {
 
     #[test]
     fn test_writing_to_vram() {
-        let mut ppu = Ppu::new();
+        let mut ppu = new_ppu();
 
         ppu.read_status();
         ppu.write_vram_addr(0x21);
@@ -411,7 +727,7 @@ mod tests {
 
     #[test]
     fn test_writing_to_spr_ram() {
-        let mut ppu = Ppu::new();
+        let mut ppu = new_ppu();
 
         ppu.write_spr_ram_addr(0x08);
         ppu.write_spr_ram_data(0xff);
@@ -420,4 +736,182 @@ mod tests {
         assert_eq!(ppu.spr_ram[0x08 as usize], 0xff);
         assert_eq!(ppu.spr_ram[0x09 as usize], 0xfe);
     }
+
+    fn write_vram(ppu: &mut Ppu, addr: u16, value: u8) {
+        ppu.read_status();
+        ppu.write_vram_addr((addr >> 8) as u8);
+        ppu.write_vram_addr(addr as u8);
+        ppu.write_vram_data(value);
+    }
+
+    fn step_to(ppu: &mut Ppu, scanline: i16, cycle: u16) {
+        while ppu.scanline != scanline || ppu.cycle != cycle {
+            ppu.step();
+        }
+    }
+
+    #[test]
+    fn test_sprite_palette_mirroring() {
+        let mut ppu = new_ppu();
+
+        write_vram(&mut ppu, 0x3f10, 0x21);
+        write_vram(&mut ppu, 0x3f15, 0x16);
+
+        assert_eq!(ppu.vram.read(0x3f00), 0x21);
+        assert_eq!(ppu.vram.read(0x3f15), 0x16);
+        assert_eq!(ppu.vram.read(0x3f35), 0x16);
+    }
+
+    #[test]
+    fn test_sprite_rendering() {
+        let mut ppu = new_ppu();
+
+        write_vram(&mut ppu, 0x0010, 0x80);
+        write_vram(&mut ppu, 0x3f00, 0x0f);
+        write_vram(&mut ppu, 0x3f11, 0x30);
+        ppu.spr_ram[0..4].copy_from_slice(&[9, 1, 0, 20]);
+        ppu.spr_ram[4..8].copy_from_slice(&[9, 1, SPRITE_ATTR_FLIP_HORIZONTAL, 40]);
+        ppu.write_mask(MASK_DISPLAY_SPRITES);
+
+        step_to(&mut ppu, 11, 0);
+
+        assert_eq!(ppu.screen[256 * 9 + 20], 0);
+        assert_eq!(ppu.screen[256 * 10 + 20], SYSTEM_PALETTE[0x30]);
+        assert_eq!(ppu.screen[256 * 10 + 21], SYSTEM_PALETTE[0x0f]);
+        assert_eq!(ppu.screen[256 * 10 + 40], SYSTEM_PALETTE[0x0f]);
+        assert_eq!(ppu.screen[256 * 10 + 47], SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut ppu = new_ppu();
+
+        write_vram(&mut ppu, 0x0010, 0xff);
+        write_vram(&mut ppu, 0x0012, 0xff);
+        write_vram(&mut ppu, 0x2020, 0x01);
+        ppu.write_ctrl(0);
+        ppu.write_scroll(0);
+        ppu.write_scroll(0);
+        ppu.spr_ram[0..4].copy_from_slice(&[9, 1, 0, 4]);
+        ppu.write_mask(MASK_DISPLAY_BACKGROUND | MASK_DISPLAY_SPRITES);
+
+        step_to(&mut ppu, 11, 0);
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT_FLAG, 0);
+
+        ppu.write_mask(MASK_DISPLAY_BACKGROUND | MASK_DISPLAY_SPRITES | MASK_BACKGROUND_LEFT |
+                       MASK_SPRITES_LEFT);
+
+        step_to(&mut ppu, 10, 5);
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT_FLAG, 0);
+        step_to(&mut ppu, 10, 6);
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT_FLAG,
+                   STATUS_SPRITE_ZERO_HIT_FLAG);
+
+        step_to(&mut ppu, 261, 2);
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT_FLAG, 0);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = new_ppu();
+
+        for n in 0..8 {
+            ppu.spr_ram[n * 4] = 10;
+        }
+        // The ninth sprite is out of range, but the buggy evaluation reads the
+        // tenth sprite's tile number as its Y coordinate.
+        ppu.spr_ram[8 * 4..10 * 4].copy_from_slice(&[100, 0, 0, 0, 100, 5, 0, 0]);
+        for n in 10..64 {
+            ppu.spr_ram[n * 4] = 0xff;
+        }
+        ppu.write_mask(MASK_DISPLAY_SPRITES);
+
+        step_to(&mut ppu, 10, 255);
+        assert_eq!(ppu.status & STATUS_SPRITE_OVERFLOW_FLAG, 0);
+        step_to(&mut ppu, 10, 257);
+        assert_eq!(ppu.status & STATUS_SPRITE_OVERFLOW_FLAG,
+                   STATUS_SPRITE_OVERFLOW_FLAG);
+
+        step_to(&mut ppu, 261, 2);
+        assert_eq!(ppu.status & STATUS_SPRITE_OVERFLOW_FLAG, 0);
+    }
+
+    #[test]
+    fn test_sprite_pattern_addr() {
+        let mut ppu = new_ppu();
+
+        ppu.scanline = 13;
+        ppu.secondary_oam[0..4].copy_from_slice(&[10, 0x21, 0, 0]);
+        ppu.secondary_oam[4..8].copy_from_slice(&[3, 0x21, SPRITE_ATTR_FLIP_VERTICAL, 0]);
+
+        assert_eq!(ppu.sprite_pattern_addr(0), 0x0213);
+
+        ppu.write_ctrl(CTRL_SPRITE_FLAG);
+        assert_eq!(ppu.sprite_pattern_addr(0), 0x1213);
+
+        ppu.write_ctrl(CTRL_SPRITE_SIZE_FLAG);
+        assert_eq!(ppu.sprite_pattern_addr(0), 0x1203);
+        assert_eq!(ppu.sprite_pattern_addr(1), 0x1205);
+        assert!(ppu.sprite_in_range(0));
+        assert!(!ppu.sprite_in_range(14));
+    }
+
+    #[test]
+    fn test_left_column_clipping() {
+        let mut ppu = new_ppu();
+
+        write_vram(&mut ppu, 0x0010, 0xff);
+        write_vram(&mut ppu, 0x3f00, 0x0f);
+        write_vram(&mut ppu, 0x3f11, 0x30);
+        ppu.spr_ram[0..4].copy_from_slice(&[9, 1, 0, 4]);
+        ppu.write_mask(MASK_DISPLAY_SPRITES);
+
+        step_to(&mut ppu, 11, 0);
+
+        assert_eq!(ppu.screen[256 * 10 + 7], SYSTEM_PALETTE[0x0f]);
+        assert_eq!(ppu.screen[256 * 10 + 8], SYSTEM_PALETTE[0x30]);
+
+        ppu.write_mask(MASK_DISPLAY_SPRITES | MASK_SPRITES_LEFT);
+        step_to(&mut ppu, 0, 0);
+        step_to(&mut ppu, 11, 0);
+
+        assert_eq!(ppu.screen[256 * 10 + 4], SYSTEM_PALETTE[0x30]);
+    }
+
+    #[test]
+    fn test_palette_color() {
+        let mut ppu = new_ppu();
+
+        assert_eq!(ppu.palette_color(0x16), SYSTEM_PALETTE[0x16]);
+
+        ppu.write_mask(MASK_GRAYSCALE);
+        assert_eq!(ppu.palette_color(0x16), SYSTEM_PALETTE[0x10]);
+
+        ppu.write_mask(MASK_EMPHASIZE_RED);
+        assert_eq!(ppu.palette_color(0x30), 0xffd0d0);
+
+        ppu.write_mask(MASK_EMPHASIZE_RED | MASK_EMPHASIZE_GREEN | MASK_EMPHASIZE_BLUE);
+        assert_eq!(ppu.palette_color(0x30), 0xd0d0d0);
+    }
+
+    #[test]
+    fn test_name_table_mirroring() {
+        let expected = [(Mirroring::Horizontal, [0x11, 0x11, 0x44, 0x44]),
+                        (Mirroring::Vertical, [0x11, 0x44, 0x11, 0x44]),
+                        (Mirroring::SingleScreenLower, [0x44, 0x44, 0x44, 0x44]),
+                        (Mirroring::SingleScreenUpper, [0x44, 0x44, 0x44, 0x44]),
+                        (Mirroring::FourScreen, [0x11, 0x00, 0x00, 0x44])];
+
+        for &(mirroring, values) in expected.iter() {
+            let mut ppu = new_ppu_with_mirroring(mirroring);
+
+            write_vram(&mut ppu, 0x2000, 0x11);
+            write_vram(&mut ppu, 0x2c00, 0x44);
+
+            for (table, &value) in values.iter().enumerate() {
+                assert_eq!(ppu.vram.read(0x2000 + table as u16 * 0x400), value);
+                assert_eq!(ppu.vram.read(0x3000 + table as u16 * 0x400), value);
+            }
+        }
+    }
 }
@@ -1,25 +1,65 @@
-pub struct Vram([u8; 16384]);
+use std::cell::RefCell;
+use std::rc::Rc;
 
-fn map_addr(addr: u16) -> usize {
-    match addr {
-        0...0x2fff | 0x3f00...0x3f1f => addr as usize,
-        0x3000...0x3eff => addr as usize - 0x1000,
-        0x3f20...0x3fff => ((addr - 0x3f20) % 32 + 0x3f00) as usize,
-        0x4000...0xffff => map_addr(addr % 0x4000),
-        _ => panic!("UNIMPLEMENTED ADDR {:x}", addr),
+use mapper::Mapper;
+
+pub struct Vram {
+    mapper: Rc<RefCell<Mapper>>,
+    name_tables: [u8; 4096],
+    palette: [u8; 32],
+}
+
+// $3F10, $3F14, $3F18 and $3F1C are mirrors of the background entries below them.
+fn map_palette_addr(addr: u16) -> usize {
+    let index = (addr - 0x3f00) as usize % 32;
+
+    if index >= 16 && index % 4 == 0 {
+        index - 16
+    } else {
+        index
     }
 }
 
 impl Vram {
-    pub fn new() -> Vram {
-        Vram([0; 16384])
+    pub fn new(mapper: Rc<RefCell<Mapper>>) -> Vram {
+        Vram {
+            mapper: mapper,
+            name_tables: [0; 4096],
+            palette: [0; 32],
+        }
     }
 
     pub fn read(&self, addr: u16) -> u8 {
-        self.0[map_addr(addr)]
+        match addr % 0x4000 {
+            addr @ 0...0x1fff => {
+                let mut mapper = self.mapper.borrow_mut();
+                let value = mapper.chr_read(addr);
+                mapper.ppu_bus_access(addr);
+                value
+            }
+            addr @ 0x2000...0x3eff => {
+                let mut mapper = self.mapper.borrow_mut();
+                let value = mapper.name_table_read(addr, &self.name_tables);
+                mapper.ppu_bus_access(addr);
+                value
+            }
+            addr => self.palette[map_palette_addr(addr)],
+        }
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
-        self.0[map_addr(addr)] = value;
+        match addr % 0x4000 {
+            addr @ 0...0x1fff => {
+                let mut mapper = self.mapper.borrow_mut();
+                mapper.ppu_bus_access(addr);
+                mapper.chr_write(addr, value);
+            }
+            addr @ 0x2000...0x3eff => {
+                let mut mapper = self.mapper.borrow_mut();
+                mapper.ppu_bus_access(addr);
+                mapper.name_table_write(addr, value, &mut self.name_tables);
+            }
+            addr => self.palette[map_palette_addr(addr)] = value,
+        }
     }
 }
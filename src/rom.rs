@@ -1,42 +1,401 @@
+use std::error;
+use std::fmt;
 use std::io::{self, Read};
 use std::path;
 use std::fs;
 
+const PRG_ROM_BANK_SIZE: usize = 16384;
+const CHR_ROM_BANK_SIZE: usize = 8192;
+pub const TRAINER_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
+/// Which revision of the header the image was made with. Archaic iNES images often have
+/// a ripper's signature such as "DiskDude!" in bytes 7-15, so only byte 6 is trusted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderFormat {
+    ArchaicINes,
+    INes,
+    Nes20,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// One of the NES 2.0 extended console types, numbered as in header byte 13.
+    Extended(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/// Why an image couldn't be loaded or run.
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    InvalidMagic,
+    TruncatedTrainer,
+    MissingPrgRom,
+    TruncatedPrgRom { expected: usize, found: usize },
+    TruncatedChrRom { expected: usize, found: usize },
+    UnsupportedMapper(u16),
+    UnsupportedConsoleType(ConsoleType),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Io(ref e) => write!(f, "{}", e),
+            RomError::InvalidMagic => write!(f, "Not an iNES or NES 2.0 image"),
+            RomError::TruncatedTrainer => write!(f, "The trainer is cut short"),
+            RomError::MissingPrgRom => write!(f, "The image has less than 8K of PRG ROM"),
+            RomError::TruncatedPrgRom { expected, found } => {
+                write!(f, "PRG ROM is cut short: expected {} bytes, found {}", expected, found)
+            }
+            RomError::TruncatedChrRom { expected, found } => {
+                write!(f, "CHR ROM is cut short: expected {} bytes, found {}", expected, found)
+            }
+            RomError::UnsupportedMapper(mapper) => write!(f, "Unsupported mapper {}", mapper),
+            RomError::UnsupportedConsoleType(console_type) => {
+                write!(f, "Unsupported console type {:?}", console_type)
+            }
+        }
+    }
+}
+
+impl error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> RomError {
+        RomError::Io(e)
+    }
+}
+
 pub struct Rom {
     pub prg_rom: Vec<Vec<u8>>,
     pub chr_rom: Vec<Vec<u8>>,
-    pub mapper: u8,
+    /// The 512 bytes some images carry for $7000-$71FF, often from copier hardware.
+    pub trainer: Option<Vec<u8>>,
+    /// Anything NES 2.0 images store after CHR ROM, such as PlayChoice-10 hint screens.
+    pub misc_rom: Vec<u8>,
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    /// The board variant within the mapper, or 0 when the header doesn't say.
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    /// Whether the cartridge keeps its PRG RAM alive with a battery.
+    pub battery: bool,
+    /// RAM sizes in bytes, split into volatile RAM and battery-backed NVRAM. Only NES 2.0
+    /// headers give them, so they are zero for older images.
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub console_type: ConsoleType,
+    pub timing: Timing,
+}
+
+impl Default for Rom {
+    fn default() -> Rom {
+        Rom {
+            prg_rom: Vec::new(),
+            chr_rom: Vec::new(),
+            trainer: None,
+            misc_rom: Vec::new(),
+            format: HeaderFormat::INes,
+            mapper: 0,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            console_type: ConsoleType::Nes,
+            timing: Timing::Ntsc,
+        }
+    }
 }
 
 impl Rom {
-    pub fn load<P: AsRef<path::Path>>(filename: P) -> io::Result<Rom> {
-        let mut file = fs::File::open(filename)?;
+    pub fn load<P: AsRef<path::Path>>(filename: P) -> Result<Rom, RomError> {
+        Rom::from_bytes(&fs::read(filename)?)
+    }
+
+    /// Parses an image that's already in memory, such as one from `include_bytes!`.
+    pub fn from_bytes(data: &[u8]) -> Result<Rom, RomError> {
+        Rom::from_reader(data)
+    }
 
+    /// Parses an image from any byte stream. Nothing past the end of the image is read,
+    /// except for NES 2.0 images with misc ROM, which runs to the end of the stream.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Rom, RomError> {
         let mut header = [0u8; 16];
-        file.read_exact(&mut header)?;
-
-        if header[0..4] != *b"NES\x1a" {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid ROM"));
-        }
-
-        let prg_rom = (0..header[4]).map(|_| {
-                let mut buf = vec![0u8; 16384];
-                file.read_exact(&mut buf)?;
-                Ok(buf)
-            })
-            .collect::<io::Result<Vec<Vec<u8>>>>()?;
-
-        let chr_rom = (0..header[5]).map(|_| {
-                let mut buf = vec![0u8; 8192];
-                file.read_exact(&mut buf)?;
-                Ok(buf)
-            })
-            .collect::<io::Result<Vec<Vec<u8>>>>()?;
-
-        Ok(Rom {
-            prg_rom: prg_rom,
-            chr_rom: chr_rom,
-            mapper: (header[7] & 0xf0) + (header[6] >> 4),
-        })
+        let header_bytes = read_to_size(&mut reader, header.len())?;
+        if header_bytes.len() < header.len() || header_bytes[0..4] != *b"NES\x1a" {
+            return Err(RomError::InvalidMagic);
+        }
+        header.copy_from_slice(&header_bytes);
+
+        let mut rom = Rom::from_header(&header);
+
+        if header[6] & 0x04 != 0 {
+            let trainer = read_to_size(&mut reader, TRAINER_SIZE)?;
+            if trainer.len() < TRAINER_SIZE {
+                return Err(RomError::TruncatedTrainer);
+            }
+            rom.trainer = Some(trainer);
+        }
+
+        let (prg_rom_size, chr_rom_size) = rom_sizes(&header, rom.format);
+        // Every board maps at least one 8K bank at the top of the address space, where the
+        // vectors live.
+        if prg_rom_size < 0x2000 {
+            return Err(RomError::MissingPrgRom);
+        }
+
+        let prg_rom = read_to_size(&mut reader, prg_rom_size)?;
+        if prg_rom.len() < prg_rom_size {
+            return Err(RomError::TruncatedPrgRom {
+                expected: prg_rom_size,
+                found: prg_rom.len(),
+            });
+        }
+        rom.prg_rom = prg_rom.chunks(PRG_ROM_BANK_SIZE).map(|bank| bank.to_vec()).collect();
+
+        let chr_rom = read_to_size(&mut reader, chr_rom_size)?;
+        if chr_rom.len() < chr_rom_size {
+            return Err(RomError::TruncatedChrRom {
+                expected: chr_rom_size,
+                found: chr_rom.len(),
+            });
+        }
+        rom.chr_rom = chr_rom.chunks(CHR_ROM_BANK_SIZE).map(|bank| bank.to_vec()).collect();
+
+        if rom.format == HeaderFormat::Nes20 && header[14] & 0x03 != 0 {
+            reader.read_to_end(&mut rom.misc_rom)?;
+        }
+
+        Ok(rom)
+    }
+
+    fn from_header(header: &[u8; 16]) -> Rom {
+        let format = header_format(header);
+
+        let mirroring = if header[6] & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if header[6] & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mut rom = Rom {
+            format: format,
+            mapper: (header[6] >> 4) as u16,
+            mirroring: mirroring,
+            battery: header[6] & 0x02 != 0,
+            ..Rom::default()
+        };
+
+        match format {
+            HeaderFormat::ArchaicINes => {}
+            HeaderFormat::INes => {
+                rom.mapper |= (header[7] & 0xf0) as u16;
+                rom.console_type = match header[7] & 0x03 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    _ => ConsoleType::Playchoice10,
+                };
+                if header[9] & 0x01 != 0 {
+                    rom.timing = Timing::Pal;
+                }
+            }
+            HeaderFormat::Nes20 => {
+                rom.mapper |= (header[7] & 0xf0) as u16 | ((header[8] & 0x0f) as u16) << 8;
+                rom.submapper = header[8] >> 4;
+                rom.prg_ram_size = ram_size(header[10] & 0x0f);
+                rom.prg_nvram_size = ram_size(header[10] >> 4);
+                rom.chr_ram_size = ram_size(header[11] & 0x0f);
+                rom.chr_nvram_size = ram_size(header[11] >> 4);
+                rom.console_type = match header[7] & 0x03 {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    2 => ConsoleType::Playchoice10,
+                    _ => ConsoleType::Extended(header[13] & 0x0f),
+                };
+                rom.timing = match header[12] & 0x03 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+            }
+        }
+
+        rom
+    }
+
+    /// The amount of PRG RAM the board should have: what a NES 2.0 header asks for, or
+    /// `default` when the header doesn't say.
+    pub fn prg_ram_size_or(&self, default: usize) -> usize {
+        match self.prg_ram_size + self.prg_nvram_size {
+            0 => default,
+            size => size,
+        }
+    }
+}
+
+fn header_format(header: &[u8; 16]) -> HeaderFormat {
+    match header[7] & 0x0c {
+        0x08 => HeaderFormat::Nes20,
+        0x00 if header[12..16].iter().all(|&byte| byte == 0) => HeaderFormat::INes,
+        _ => HeaderFormat::ArchaicINes,
+    }
+}
+
+fn rom_sizes(header: &[u8; 16], format: HeaderFormat) -> (usize, usize) {
+    if format == HeaderFormat::Nes20 {
+        (nes20_rom_size(header[4], header[9] & 0x0f, PRG_ROM_BANK_SIZE),
+         nes20_rom_size(header[5], header[9] >> 4, CHR_ROM_BANK_SIZE))
+    } else {
+        (header[4] as usize * PRG_ROM_BANK_SIZE, header[5] as usize * CHR_ROM_BANK_SIZE)
+    }
+}
+
+/// NES 2.0 sizes are a 12-bit count of banks, unless the high nibble is all ones, in
+/// which case the low byte holds an exponent and a multiplier for odd sizes.
+fn nes20_rom_size(low: u8, high: u8, bank_size: usize) -> usize {
+    if high == 0x0f {
+        let exponent = (low >> 2) as u32;
+        let multiplier = (low & 0x03) as usize * 2 + 1;
+        1usize.checked_shl(exponent).unwrap_or(usize::max_value()).saturating_mul(multiplier)
+    } else {
+        ((high as usize) << 8 | low as usize) * bank_size
+    }
+}
+
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// Reads up to `size` bytes without trusting `size` enough to allocate it up front.
+fn read_to_size<R: Read>(reader: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(size as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn header(bytes: &[u8]) -> [u8; 16] {
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(b"NES\x1a");
+        header[4..4 + bytes.len()].copy_from_slice(bytes);
+        header
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let rom = Rom::from_header(&header(&[2, 1, 0x13, 0x40, 0, 0x01]));
+        assert_eq!(rom.format, HeaderFormat::INes);
+        assert_eq!(rom.mapper, 0x41);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom_sizes(&header(&[2, 1]), rom.format), (32768, 8192));
+    }
+
+    #[test]
+    fn test_archaic_header() {
+        let mut bytes = header(&[2, 1, 0x10]);
+        bytes[7..16].copy_from_slice(b"DiskDude!");
+        let rom = Rom::from_header(&bytes);
+        assert_eq!(rom.format, HeaderFormat::ArchaicINes);
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_nes20_header() {
+        let bytes = header(&[0x07, 0x00, 0x52, 0x1b, 0x21, 0xf0, 0x70, 0x07, 0x03, 0x04]);
+        let rom = Rom::from_header(&bytes);
+        assert_eq!(rom.format, HeaderFormat::Nes20);
+        assert_eq!(rom.mapper, 0x115);
+        assert_eq!(rom.submapper, 2);
+        assert_eq!(rom.prg_ram_size_or(1), 8192);
+        assert_eq!(rom.prg_nvram_size, 8192);
+        assert_eq!(rom.chr_ram_size, 8192);
+        assert_eq!(rom.console_type, ConsoleType::Extended(4));
+        assert_eq!(rom.timing, Timing::Dendy);
+        assert_eq!(rom_sizes(&bytes, rom.format), (7 * 16384, 1));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        match Rom::from_bytes(b"NES") {
+            Err(RomError::InvalidMagic) => {}
+            _ => panic!("Expected InvalidMagic"),
+        }
+
+        let mut data = header(&[2, 1]).to_vec();
+        data.extend(vec![0; 20000]);
+        match Rom::from_bytes(&data) {
+            Err(RomError::TruncatedPrgRom { expected: 32768, found: 20000 }) => {}
+            _ => panic!("Expected TruncatedPrgRom"),
+        }
+
+        let mut data = header(&[0, 1]).to_vec();
+        data.extend(vec![0; 8192]);
+        match Rom::from_bytes(&data) {
+            Err(RomError::MissingPrgRom) => {}
+            _ => panic!("Expected MissingPrgRom"),
+        }
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut data = header(&[1, 0, 0x04]).to_vec();
+        data.extend(vec![0xaa; TRAINER_SIZE]);
+        data.extend(vec![0x55; PRG_ROM_BANK_SIZE]);
+        let rom = Rom::from_bytes(&data).unwrap();
+
+        assert_eq!(rom.trainer, Some(vec![0xaa; TRAINER_SIZE]));
+        assert_eq!(rom.prg_rom, vec![vec![0x55; PRG_ROM_BANK_SIZE]]);
+    }
+
+    #[test]
+    fn test_load_from_file() {
+        let mut data = header(&[1, 1, 0x01]).to_vec();
+        data.extend(vec![0x11; PRG_ROM_BANK_SIZE]);
+        data.extend(vec![0x22; CHR_ROM_BANK_SIZE]);
+        let filename = env::temp_dir().join("nes-rs-load-test.nes");
+        fs::write(&filename, &data).unwrap();
+
+        let rom = Rom::load(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert_eq!(rom.chr_rom, vec![vec![0x22; CHR_ROM_BANK_SIZE]]);
     }
 }
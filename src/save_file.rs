@@ -0,0 +1,140 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use nes::Nes;
+
+/// Keeps a cartridge's battery-backed RAM in a `.sav` file next to the ROM. Carts
+/// without a battery have nothing to save, so both operations do nothing for them.
+pub struct SaveFile {
+    path: PathBuf,
+    last_saved: Option<Vec<u8>>,
+}
+
+impl SaveFile {
+    pub fn for_rom<P: AsRef<Path>>(rom_filename: P) -> SaveFile {
+        SaveFile {
+            path: rom_filename.as_ref().with_extension("sav"),
+            last_saved: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Copies a saved RAM image into the cartridge. A missing save is not an error, and the
+    /// RAM is left untouched if the save can't be read.
+    pub fn load(&mut self, nes: &mut Nes) -> io::Result<()> {
+        if nes.battery_ram().is_none() {
+            return Ok(());
+        }
+
+        let mut file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        nes.load_battery_ram(&data);
+        self.last_saved = nes.battery_ram();
+        Ok(())
+    }
+
+    /// Writes the RAM out if it has changed since it was last loaded or saved. The data goes
+    /// to a temporary file that is then renamed over the save, so a failed write can't leave
+    /// a truncated save behind.
+    pub fn save(&mut self, nes: &Nes) -> io::Result<()> {
+        let ram = match nes.battery_ram() {
+            Some(ram) => ram,
+            None => return Ok(()),
+        };
+
+        if self.last_saved.as_ref() == Some(&ram) {
+            return Ok(());
+        }
+
+        let temp_path = self.path.with_extension("sav.tmp");
+        fs::write(&temp_path, &ram)?;
+        fs::rename(&temp_path, &self.path)?;
+        self.last_saved = Some(ram);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use interconnect::Interconnect;
+    use rom::{Mirroring, Rom};
+
+    fn new_nes(battery: bool) -> Nes {
+        Nes::new(Rom {
+            prg_rom: vec![vec![0; 16384]],
+            chr_rom: vec![vec![0; 8192]],
+            mapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: battery,
+            ..Rom::default()
+        })
+            .unwrap()
+    }
+
+    // A ROM path unique to this process, whose save files are removed even if the test fails.
+    struct TempRom(PathBuf);
+
+    impl TempRom {
+        fn new(name: &str) -> TempRom {
+            TempRom(env::temp_dir().join(format!("nes-rs-{}-{}.nes", name, process::id())))
+        }
+    }
+
+    impl Drop for TempRom {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(self.0.with_extension("sav"));
+            let _ = fs::remove_file(self.0.with_extension("sav.tmp"));
+            let _ = fs::remove_dir(self.0.with_extension("sav"));
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let rom = TempRom::new("save-file-test");
+        let rom_filename = &rom.0;
+        let mut save_file = SaveFile::for_rom(rom_filename);
+
+        let mut nes = new_nes(true);
+        nes.interconnect.write_word(0x6010, 0x42);
+        save_file.save(&nes).unwrap();
+
+        let mut nes = new_nes(true);
+        SaveFile::for_rom(rom_filename).load(&mut nes).unwrap();
+        assert_eq!(nes.interconnect.read_word(0x6010), 0x42);
+        assert!(!rom_filename.with_extension("sav.tmp").exists());
+    }
+
+    #[test]
+    fn test_no_battery() {
+        let rom = TempRom::new("no-battery-test");
+        let rom_filename = &rom.0;
+        let mut nes = new_nes(false);
+        nes.interconnect.write_word(0x6010, 0x42);
+        SaveFile::for_rom(rom_filename).save(&nes).unwrap();
+
+        assert!(!rom_filename.with_extension("sav").exists());
+    }
+
+    #[test]
+    fn test_unreadable_save() {
+        let rom = TempRom::new("unreadable-save-test");
+        fs::create_dir(rom.0.with_extension("sav")).unwrap();
+
+        let mut nes = new_nes(true);
+        assert!(SaveFile::for_rom(&rom.0).load(&mut nes).is_err());
+        assert_eq!(nes.interconnect.read_word(0x6010), 0);
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+
+/// Streams mono 16-bit PCM samples to a RIFF WAVE file. The chunk sizes in the header are
+/// filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        writer.write_all(b"RIFF")?;
+        write_u32(&mut writer, 0)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        write_u32(&mut writer, 16)?;
+        write_u16(&mut writer, 1)?; // PCM
+        write_u16(&mut writer, 1)?; // Mono
+        write_u32(&mut writer, sample_rate)?;
+        write_u32(&mut writer, sample_rate * 2)?;
+        write_u16(&mut writer, 2)?;
+        write_u16(&mut writer, 16)?;
+
+        writer.write_all(b"data")?;
+        write_u32(&mut writer, 0)?;
+
+        Ok(WavWriter {
+            writer: writer,
+            data_size: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let value = (sample.max(-1.0).min(1.0) * i16::max_value() as f32) as i16;
+            write_u16(&mut self.writer, value as u16)?;
+        }
+
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        write_u32(&mut self.writer, HEADER_SIZE - 8 + self.data_size)?;
+        self.writer.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        write_u32(&mut self.writer, self.data_size)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    write_u16(writer, value as u16)?;
+    write_u16(writer, (value >> 16) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_wav() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write_samples(&[0.0, 1.0, -2.0]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 50);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &[42, 0, 0, 0]);
+        assert_eq!(&bytes[24..28], &[0x44, 0xac, 0, 0]);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(&bytes[40..44], &[6, 0, 0, 0]);
+        assert_eq!(&bytes[44..], &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}